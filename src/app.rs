@@ -1,44 +1,169 @@
+use crate::checksum::Checksum;
+use crate::config::Config;
+use crate::defmt::DefmtDecoder;
+use crate::escape::{parse_hex, unescape};
+use crate::filter::LineFilter;
+use crate::framing::FrameDecoder;
+use crate::highlight::Highlighter;
+use crate::history::History;
+use crate::input::{EditResult, LineEditor};
+use crate::keys::{EraseCode, KeyEncoder, Keymap};
+use crate::macros::{self, Macro, MacroPlayer};
+use crate::modbus::{self, ModbusDecoder};
+use crate::newline::RxMap;
+use crate::nmea::{self, NmeaDecoder};
+use crate::plot::Plot;
+use crate::rx_filter::{FilterOutput, RxFilter};
+use crate::script::{Action, Script};
+use crate::stats::Stats;
+use crate::trigger::{self, CaptureAction, DtrAction, TriggerAction, Triggers};
 use crate::tui::Tui;
 use crate::Cli;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{crate_name, crate_version};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use std::io::Write;
-use tokio_serial::SerialStream;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+use time::{OffsetDateTime, UtcOffset};
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use tokio_serial::{SerialPort, SerialStream};
 
 // TODO add support for logging to a file?
 // TODO add support to paste a file?
 // TODO Allow setting the serialport options? (or cmdline only)
 
 #[derive(Clone, Copy, PartialEq)]
-enum Commands {
+pub enum Commands {
     Quit,
     Exit,
     ToggleLocalEcho,
-    ToggleLineFeed,
-    ToggleCarriageReturn,
+    CycleRxMap,
+    CycleTxEnter,
     ToggleTimestamp,
     ClearScreen,
     ShowHelp,
+    RunScript,
+    ToggleBackspace,
+    CycleDelete,
+    ToggleLineEdit,
+    SendHex,
+    ToggleHex,
+    Filter,
+    ToggleCapture,
+    RxFilter,
+    Modbus,
+    ShowPlot,
+    ShowStats,
+}
+impl Commands {
+    pub const ALL: [Commands; 20] = [
+        Commands::Quit,
+        Commands::Exit,
+        Commands::ToggleLocalEcho,
+        Commands::CycleRxMap,
+        Commands::CycleTxEnter,
+        Commands::ToggleTimestamp,
+        Commands::ClearScreen,
+        Commands::ShowHelp,
+        Commands::RunScript,
+        Commands::ToggleBackspace,
+        Commands::CycleDelete,
+        Commands::ToggleLineEdit,
+        Commands::SendHex,
+        Commands::ToggleHex,
+        Commands::Filter,
+        Commands::ToggleCapture,
+        Commands::RxFilter,
+        Commands::Modbus,
+        Commands::ShowPlot,
+        Commands::ShowStats,
+    ];
+
+    pub fn default_key(&self) -> char {
+        use Commands::*;
+        match *self {
+            Quit => 'q',
+            Exit => 'x',
+            ToggleLocalEcho => 'e',
+            CycleRxMap => 'a',
+            CycleTxEnter => 't',
+            ToggleTimestamp => 'n',
+            ClearScreen => 'c',
+            ShowHelp => 'z',
+            RunScript => 'g',
+            ToggleBackspace => 'b',
+            CycleDelete => 'd',
+            ToggleLineEdit => 'i',
+            SendHex => 'h',
+            ToggleHex => 'v',
+            Filter => 'f',
+            ToggleCapture => 'l',
+            RxFilter => 'r',
+            Modbus => 'm',
+            ShowPlot => 'p',
+            ShowStats => 's',
+        }
+    }
+
+    /// Name used in the [keys] section of the config file
+    pub fn name(&self) -> &'static str {
+        use Commands::*;
+        match *self {
+            Quit => "quit",
+            Exit => "exit",
+            ToggleLocalEcho => "toggle_local_echo",
+            CycleRxMap => "cycle_rx_map",
+            CycleTxEnter => "cycle_tx_enter",
+            ToggleTimestamp => "toggle_timestamp",
+            ClearScreen => "clear_screen",
+            ShowHelp => "show_help",
+            RunScript => "run_script",
+            ToggleBackspace => "toggle_backspace",
+            CycleDelete => "cycle_delete",
+            ToggleLineEdit => "toggle_line_edit",
+            SendHex => "send_hex",
+            ToggleHex => "toggle_hex",
+            Filter => "filter",
+            ToggleCapture => "toggle_capture",
+            RxFilter => "rx_filter",
+            Modbus => "modbus",
+            ShowPlot => "show_plot",
+            ShowStats => "show_stats",
+        }
+    }
+
+    pub fn desc(&self) -> &'static str {
+        use Commands::*;
+        match *self {
+            Quit => "Quit",
+            Exit => "Exit",
+            ToggleLocalEcho => "Toggle local echo",
+            CycleRxMap => "Cycle the newline mapping of received data",
+            CycleTxEnter => "Cycle what Enter sends (CR, CRLF, LF)",
+            ToggleTimestamp => "Cycle timestamps",
+            ClearScreen => "Clear screen",
+            ShowHelp => "Show this help",
+            RunScript => "Run a script (or stop the running one)",
+            ToggleBackspace => "Toggle the Backspace key between BS and DEL",
+            CycleDelete => "Cycle what the Delete key sends (VT220, DEL, BS)",
+            ToggleLineEdit => "Toggle local line editing, lines are sent on Enter",
+            SendHex => "Send hex bytes or an escaped string (+crc16, +crc8, +sum8)",
+            ToggleHex => "Toggle showing received data as hex",
+            Filter => "Only show lines matching a regex (!regex hides them)",
+            ToggleCapture => "Start or stop capturing received data to a file",
+            RxFilter => "Pipe received data through a command (or stop it)",
+            Modbus => "Send a Modbus RTU request",
+            ShowPlot => "Plot the numbers in the received lines",
+            ShowStats => "Show RX/TX statistics and line errors",
+        }
+    }
 }
-// impl Commands {
-//     fn key(&self) -> char {
-//         use Commands::*;
-//         match *self {
-//             Quit => 'q',
-//             Exit => 'x',
-//             ToggleLocalEcho => 'e',
-//             ToggleLineFeed => 'a',
-//             ToggleCarriageReturn => 'u',
-//             ToggleTimestamp => 'n',
-//             ClearScreen => 'c',
-//             ShowHelp => 'z',
-//         }
-//     }
-//     fn desc(&self) -> &'static str {
-//         return "test";
-//     }
-// }
 
 trait OptionAsString {
     fn val_to_str(&self) -> &'static str;
@@ -57,43 +182,19 @@ impl OptionAsString for Timestamp {
         match *self {
             Timestamp::Simple => "Simple",
             Timestamp::Extend => "Extended",
+            Timestamp::Elapsed => "Elapsed",
+            Timestamp::Delta => "Delta",
+            Timestamp::Iso => "ISO 8601",
             Timestamp::Off => "Off",
         }
     }
 }
 
-fn get_command(cmd: char) -> Option<Commands> {
-    use Commands::*;
-    match cmd {
-        'q' => Some(Quit),
-        'x' => Some(Exit),
-        'e' => Some(ToggleLocalEcho),
-        'a' => Some(ToggleLineFeed),
-        'u' => Some(ToggleCarriageReturn),
-        'n' => Some(ToggleTimestamp),
-        'c' => Some(ClearScreen),
-        'z' => Some(ShowHelp),
-        _ => None,
-    }
-}
-// #[cfg(test)]
-// mod tests {
-//     use crate::app::get_command;
-
-//     #[test]
-//     fn matches() {
-//         for c in 'a'..'z' {
-//             if let Some(cmd) = get_command(c) {
-//                 assert!(cmd.key() == c);
-//             }
-//         }
-//     }
-// }
-
 struct MyOptions {
-    add_carriage_return: bool,
-    add_line_feed: bool,
+    rx_map: RxMap,
     local_echo: bool,
+    line_edit: bool,
+    hex: bool,
     timestamp: Timestamp,
 }
 
@@ -101,15 +202,68 @@ pub struct App {
     state: AppStates,
     tui: Tui,
     cli: Cli,
+    keymap: Keymap,
+    encoder: KeyEncoder,
+    macros: Vec<Macro>,
+    macro_player: MacroPlayer,
     status_delay: u64,
     opts: MyOptions,
+    editor: LineEditor,
+    line_editor: LineEditor,
+    history: History,
+    script: Option<Script>,
+    last_script: String,
+    last_hex: String,
+    /* Last received data and whether the idle gap after it was handled */
+    last_rx: Option<Instant>,
+    idle: bool,
+    capture: Option<(File, String)>,
+    triggers: Triggers,
+    /* DTR is set when the port is opened */
+    dtr: bool,
+    rx_filter: Option<RxFilter>,
+    last_rx_filter: String,
+    filter_ids: u32,
+    filter_output: mpsc::Sender<FilterOutput>,
+    /* Taken by the event loop */
+    filter_output_rx: Option<mpsc::Receiver<FilterOutput>>,
+    defmt: Option<DefmtDecoder>,
+    frames: Option<FrameDecoder>,
+    modbus: Option<ModbusDecoder>,
+    last_modbus: String,
+    nmea: Option<NmeaDecoder>,
+    plot: Plot,
+    stats: Stats,
 }
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum AppStates {
     Receiving,
     MenuActive,
+    PlotActive,
+    StatsActive,
     CatchKey,
+    Prompt(PromptKind),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    RunScript,
+    SendHex,
+    Filter,
+    RxFilter,
+    Modbus,
+}
+impl PromptKind {
+    fn label(&self) -> &'static str {
+        match *self {
+            PromptKind::RunScript => "Run script:",
+            PromptKind::SendHex => "Send hex:",
+            PromptKind::Filter => "Filter:",
+            PromptKind::RxFilter => "RX filter command:",
+            PromptKind::Modbus => "Modbus (slave function args):",
+        }
+    }
 }
 
 pub enum AppResults {
@@ -121,13 +275,22 @@ pub enum AppResults {
 pub enum Timestamp {
     Simple,
     Extend,
+    /// Since the start of the session
+    Elapsed,
+    /// Since the start of the previous line
+    Delta,
+    /// Date and time with the UTC offset
+    Iso,
     Off,
 }
 impl Timestamp {
     fn next(&self) -> Self {
         match *self {
             Timestamp::Simple => Timestamp::Extend,
-            Timestamp::Extend => Timestamp::Simple,
+            Timestamp::Extend => Timestamp::Elapsed,
+            Timestamp::Elapsed => Timestamp::Delta,
+            Timestamp::Delta => Timestamp::Iso,
+            Timestamp::Iso => Timestamp::Off,
             Timestamp::Off => Timestamp::Simple,
         }
     }
@@ -189,32 +352,143 @@ impl Timestamp {
 pub const TICKS_MS: u64 = 100;
 const STATUS_DELAY_MS: u64 = 3000;
 const STATUS_DELAY_TICKS: u64 = STATUS_DELAY_MS / TICKS_MS;
+/* The line filter decides on a partial line after this much silence */
+const FILTER_IDLE_MS: u64 = 300;
+const LINE_EDIT_LABEL: &str = "Send:";
+/* Capture file when none is given with --capture */
+const CAPTURE_NAME: &[time::format_description::FormatItem<'static>] =
+    time::macros::format_description!("minircom-[year][month][day]-[hour][minute][second].log");
 
 impl App {
-    pub fn init(cli: Cli) -> Result<App> {
-        let tui = Tui::init()?;
+    pub fn init(cli: Cli, config: Config, local_offset: UtcOffset, stats: Stats) -> Result<App> {
+        let keymap = Keymap::from_config(&config)?;
+        let macros = macros::from_config(&config, keymap.escape_key())?;
+        let mut encoder = KeyEncoder::default();
+        if let Some(backspace) = config.backspace {
+            encoder.backspace = backspace;
+        }
+        if let Some(delete) = config.delete {
+            encoder.delete = delete;
+        }
+        if let Some(enter) = config.tx_enter {
+            encoder.enter = enter;
+        }
+        let mut highlighter = Highlighter::from_config(config.highlight.as_deref())?;
+        if cli.nmea {
+            let (regex, style) = nmea::bad_checksum_rule();
+            highlighter.add_first(regex, style);
+        }
+        let triggers = Triggers::from_config(&config.trigger)?;
+        let (filter_output, filter_output_rx) = mpsc::channel(64);
+        let defmt = cli.defmt.as_deref().map(DefmtDecoder::open).transpose()?;
+        let frames = cli.framing.map(|framing| FrameDecoder::new(framing, cli.frame_check));
+        let modbus = cli.modbus.then(|| ModbusDecoder::new(cli.baud_rate));
+        let nmea = cli.nmea.then(NmeaDecoder::default);
+        let plot = Plot::new(config.plot.as_ref(), cli.plot_regex.as_deref(), cli.plot_columns.as_deref())?;
+        let tui = Tui::init(local_offset, highlighter)?;
 
         let opts = MyOptions {
-            add_carriage_return: false,
-            add_line_feed: false,
+            rx_map: config.rx_map.unwrap_or(RxMap::None),
             local_echo: false,
+            line_edit: false,
+            hex: false,
             timestamp: Timestamp::Off,
         };
         let mut app = App {
             state: AppStates::Receiving,
             tui,
             cli,
+            keymap,
+            encoder,
+            macros,
+            macro_player: MacroPlayer::default(),
             status_delay: 0,
             opts,
+            editor: LineEditor::default(),
+            line_editor: LineEditor::default(),
+            history: History::load(),
+            script: None,
+            last_script: String::new(),
+            last_hex: String::new(),
+            last_rx: None,
+            idle: false,
+            capture: None,
+            triggers,
+            dtr: true,
+            rx_filter: None,
+            last_rx_filter: String::new(),
+            filter_ids: 0,
+            filter_output,
+            filter_output_rx: Some(filter_output_rx),
+            defmt,
+            frames,
+            modbus,
+            last_modbus: String::new(),
+            nmea,
+            plot,
+            stats,
         };
+        if let Some(nmea) = &app.nmea {
+            app.tui.set_panel("GNSS", nmea.status_lines())?;
+        }
         app.print_startup_stuff()?;
+        if let Some(cmd) = app.cli.rx_filter.clone() {
+            app.start_rx_filter(&cmd)?;
+        }
+        if let Some(path) = app.cli.capture.clone() {
+            app.start_capture(&path.display().to_string())?;
+        }
+        if app.cli.hex {
+            app.opts.hex = true;
+            app.tui.set_hex(true)?;
+        }
+        app.update_status_info()?;
+
+        if let Some(path) = app.cli.script.clone() {
+            app.script = Some(Script::load(&path)?);
+            app.last_script = path.display().to_string();
+        }
 
         Ok(app)
     }
 
-    pub fn tick(&mut self) -> Result<()> {
+    pub fn tick(&mut self, port: &mut SerialStream) -> Result<()> {
+        self.stats.tick(port, Instant::now());
+        self.run_script(port)?;
+        self.run_macros(port)?;
+
+        /* End the line when the device went quiet, the marker follows with the next data */
+        if let (Some(gap), Some(last_rx)) = (self.cli.idle_gap, self.last_rx) {
+            if !self.idle && last_rx.elapsed() >= Duration::from_millis(gap) {
+                self.tui.break_line()?;
+                self.idle = true;
+            }
+        }
+
+        /* Prompts don't end with a newline, don't hold them back forever */
+        if self.last_rx.is_some_and(|last_rx| last_rx.elapsed() >= Duration::from_millis(FILTER_IDLE_MS)) {
+            self.tui.flush_filter()?;
+        }
+
+        /* A Modbus frame ends with silence */
+        if let Some(modbus) = self.modbus.as_mut() {
+            let lines = modbus.poll(Instant::now());
+            if !lines.is_empty() && !self.opts.hex {
+                let time = self.tui.now();
+                self.show_received(lines.as_bytes(), time)?;
+            }
+        }
+
         if self.state == AppStates::MenuActive {
-            self.tui.draw_ui()?;
+            self.tui.draw_help(&self.help_lines())?;
+            self.status_delay = 0;
+        }
+        if self.state == AppStates::PlotActive {
+            self.tui.draw_plot(&self.plot)?;
+            self.status_delay = 0;
+        }
+        if self.state == AppStates::StatsActive {
+            self.tui.draw_stats(&self.stats.lines())?;
             self.status_delay = 0;
         }
 
@@ -233,9 +507,17 @@ impl App {
         }
 
         let help = if self.tui.is_tty() {
-            "Press CTRL-A Z for help on special keys\r\n\r\n"
+            format!("Press {} for help on special keys\r\n\r\n", self.command_keys(Commands::ShowHelp))
         } else {
-            "TTY not detected, fancy menus are disabled (hint use CTRL-A Q to quit)\r\n\r\n"
+            format!(
+                "TTY not detected, fancy menus are disabled (hint use {} to quit)\r\n\r\n",
+                self.command_keys(Commands::Quit)
+            )
+        };
+
+        let port = match &self.cli.virtual_peer {
+            Some(peer) => "virtual, connect to the other end at ".to_owned() + peer,
+            None => self.cli.device.clone(),
         };
 
         let banner = "Welcome to ".to_owned()
@@ -243,14 +525,41 @@ impl App {
             + " "
             + crate_version!()
             + "\r\n\r\nPort "
-            + &self.cli.device
-            + "\r\n"
-            + help;
+            + &port
+            + "\r\n";
+        let banner = match &self.cli.listen {
+            Some(addr) => banner + "Listening on " + addr + "\r\n" + &help,
+            None => banner + &help,
+        };
 
         self.tui.print_to_screen(&banner)?;
         Ok(())
     }
 
+    fn show_plot(&mut self) -> Result<()> {
+        if !self.tui.is_tty() {
+            return Ok(());
+        }
+
+        self.tui.enter_alt()?;
+        self.state = AppStates::PlotActive;
+        self.tui.draw_plot(&self.plot)?;
+
+        Ok(())
+    }
+
+    fn show_stats(&mut self) -> Result<()> {
+        if !self.tui.is_tty() {
+            return Ok(());
+        }
+
+        self.tui.enter_alt()?;
+        self.state = AppStates::StatsActive;
+        self.tui.draw_stats(&self.stats.lines())?;
+
+        Ok(())
+    }
+
     fn show_help(&mut self) -> Result<()> {
         if !self.tui.is_tty() {
             return Ok(());
@@ -258,41 +567,455 @@ impl App {
 
         self.tui.enter_alt()?;
         self.state = AppStates::MenuActive;
-        self.tui.draw_ui()?;
+        self.tui.draw_help(&self.help_lines())?;
 
         Ok(())
     }
 
-    fn print_incoming(&mut self, buf: &[u8]) -> Result<()> {
-        // TODO refactor vec to u8
+    /// Keys to press for a command, e.g. "CTRL-A Z"
+    fn command_keys(&self, cmd: Commands) -> String {
+        match self.keymap.key_for(cmd) {
+            Some(key) => format!("{} {}", self.keymap.escape_key(), key.to_string().to_uppercase()),
+            None => format!("{} (unbound)", self.keymap.escape_key()),
+        }
+    }
 
-        let str = String::from_utf8_lossy(buf);
+    fn help_lines(&self) -> Vec<(String, String)> {
+        let escape = self.keymap.escape_key();
+        let mut lines: Vec<(String, String)> = Commands::ALL
+            .iter()
+            .filter_map(|cmd| {
+                self.keymap
+                    .key_for(*cmd)
+                    .map(|key| (key.to_string(), cmd.desc().to_string()))
+            })
+            .collect();
+        lines.push((escape.to_string(), format!("Send {}", escape)));
+
+        if !self.macros.is_empty() {
+            lines.push((String::new(), String::new()));
+            lines.push((String::new(), "Macros (without the escape key):".to_string()));
+            for mac in &self.macros {
+                /* Control characters from double quoted TOML strings would mess up the screen */
+                let text: String = mac
+                    .text()
+                    .chars()
+                    .map(|c| match c {
+                        c if c.is_control() => format!("\\x{:02x}", c as u32),
+                        c => c.to_string(),
+                    })
+                    .collect();
+                lines.push((mac.key().to_string(), format!("Send \"{}\"", text)));
+            }
+        }
+        lines
+    }
+
+    /// Received data through the decoders, returns what to show
+    fn decode_received(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
+        /* Keep decoding in hex mode, so the frames stay in sync */
+        let decoded = if let Some(defmt) = self.defmt.as_mut() {
+            Some(defmt.decode(buf))
+        } else if let Some(frames) = self.frames.as_mut() {
+            Some(frames.decode(buf))
+        } else {
+            self.modbus.as_mut().map(|modbus| modbus.feed(buf, Instant::now()))
+        };
+
+        /* NMEA stays text, only complete lines come out, bad sentences marked */
+        let mut nmea_lines = None;
+        if let Some(nmea) = self.nmea.as_mut() {
+            nmea_lines = Some(nmea.decode(buf));
+            if nmea.take_changed() {
+                self.tui.set_panel("GNSS", nmea.status_lines())?;
+            }
+        }
+        let text = nmea_lines.as_deref().unwrap_or(buf);
+
+        let shown = match (self.opts.hex, decoded) {
+            (true, _) => buf.to_vec(),
+            (false, Some(lines)) => lines.into_bytes(),
+            (false, None) => self.opts.rx_map.apply(text).into_owned(),
+        };
 
         //crappy hex
         // let dg = str.as_bytes();
         // let bla = format!("{dg:x?}\n\r");
         // self.tui.print_or_queue(&bla)?;
 
-        // TODO instead of replace, use split?
-        if self.opts.add_carriage_return && str.contains('\n') {
-            self.tui.print_or_queue(&str.replace('\n', "\r\n"))?;
-        } else if self.opts.add_line_feed && str.contains('\r') {
-            self.tui.print_or_queue(&str.replace('\r', "\r\n"))?;
+        Ok(shown)
+    }
+
+    /// What the decoders made of the received data, through the RX filter when there is one
+    fn show_received(&mut self, shown: &[u8], time: OffsetDateTime) -> Result<()> {
+        match &self.rx_filter {
+            Some(rx_filter) => {
+                if !shown.is_empty() && !rx_filter.write(shown) {
+                    self.set_status("RX filter doesn't keep up, data dropped", "")?;
+                }
+                Ok(())
+            }
+            None => self.tui.print_data(shown, time),
+        }
+    }
+
+    /// Local echo and the output of the RX filter, these don't go through the decoders
+    fn print_local(&mut self, data: &[u8], time: OffsetDateTime) -> Result<()> {
+        let mapped = match self.opts.hex {
+            true => data.into(),
+            false => self.opts.rx_map.apply(data),
+        };
+        self.tui.print_data(&mapped, time)
+    }
+
+    pub fn handle_serial_event(&mut self, port: &mut SerialStream, data: &[u8]) -> Result<()> {
+        let time = self.tui.now();
+        if let (true, Some(last_rx)) = (self.idle, self.last_rx) {
+            if !self.opts.hex {
+                let idle = last_rx.elapsed().as_secs_f64();
+                self.tui.print_message(&format!("--- {:.3}s idle ---", idle))?;
+            }
+            self.idle = false;
+        }
+        self.last_rx = Some(Instant::now());
+        self.plot.feed(data, Instant::now());
+        self.stats.received(data);
+
+        self.encoder.scan(data);
+        /* Everything goes to the capture, whatever is shown. With an RX filter
+         * its output is what gets shown and captured. */
+        if let (None, Some((file, _))) = (&self.rx_filter, self.capture.as_mut()) {
+            file.write_all(data)?;
+        }
+        let shown = self.decode_received(data)?;
+        self.show_received(&shown, time)?;
+
+        if let Some(script) = self.script.as_mut() {
+            script.feed(data);
+            self.run_script(port)?;
+        }
+
+        for action in self.triggers.feed(data) {
+            self.run_trigger_action(port, action)?;
+        }
+        Ok(())
+    }
+
+    pub fn take_filter_output(&mut self) -> mpsc::Receiver<FilterOutput> {
+        self.filter_output_rx.take().expect("filter output already taken")
+    }
+
+    fn start_rx_filter(&mut self, cmd: &str) -> Result<()> {
+        self.last_rx_filter = cmd.to_string();
+        self.filter_ids += 1;
+        self.rx_filter = Some(RxFilter::spawn(self.filter_ids, cmd, self.filter_output.clone())?);
+        self.update_status_info()
+    }
+
+    pub fn handle_filter_output(&mut self, (id, data): FilterOutput) -> Result<()> {
+        /* Leftovers of a filter that was stopped */
+        if self.rx_filter.as_ref().map(|filter| filter.id()) != Some(id) {
+            return Ok(());
+        }
+
+        if data.is_empty() {
+            if let Some(filter) = self.rx_filter.take() {
+                self.set_status("RX filter exited: ", filter.cmd())?;
+                self.update_status_info()?;
+            }
+            return Ok(());
+        }
+
+        if let Some((file, _)) = self.capture.as_mut() {
+            file.write_all(&data)?;
+        }
+        let time = self.tui.now();
+        self.print_local(&data, time)
+    }
+
+    /// Show a status message for a while
+    fn set_status(&mut self, prefix: &str, val: &str) -> Result<()> {
+        self.tui.set_status(prefix, val)?;
+        self.status_delay = STATUS_DELAY_TICKS;
+        Ok(())
+    }
+
+    fn update_status_info(&mut self) -> Result<()> {
+        let data_bits = match self.cli.data_bits {
+            tokio_serial::DataBits::Five => "5",
+            tokio_serial::DataBits::Six => "6",
+            tokio_serial::DataBits::Seven => "7",
+            tokio_serial::DataBits::Eight => "8",
+        };
+        let parity = match self.cli.parity {
+            tokio_serial::Parity::None => "N",
+            tokio_serial::Parity::Odd => "O",
+            tokio_serial::Parity::Even => "E",
+        };
+        let stop_bits = match self.cli.stop_bits {
+            tokio_serial::StopBits::One => "1",
+            tokio_serial::StopBits::Two => "2",
+        };
+        let mut info = String::new();
+        if let Some(filter) = self.tui.filter() {
+            info += &format!("Filter: {}  ", filter.text());
+        }
+        if let Some((_, path)) = &self.capture {
+            info += &format!("Capture: {}  ", path);
+        }
+        if let Some(filter) = &self.rx_filter {
+            info += &format!("RX filter: {}  ", filter.cmd());
+        }
+        info += &format!(
+            "BkSp:{} Del:{}  {} {}{}{}",
+            self.encoder.backspace.name(),
+            self.encoder.delete.name(),
+            self.cli.baud_rate,
+            data_bits,
+            parity,
+            stop_bits
+        );
+        self.tui.set_status_info(&info)
+    }
+
+    fn start_capture(&mut self, path: &str) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not open capture file {}", path))?;
+        self.capture = Some((file, path.to_string()));
+        self.update_status_info()
+    }
+
+    /// Capture file from --capture, or a new one named after the current time
+    fn capture_path(&self) -> Result<String> {
+        Ok(match &self.cli.capture {
+            Some(path) => path.display().to_string(),
+            None => self.tui.now().format(CAPTURE_NAME)?,
+        })
+    }
+
+    fn run_trigger_action(&mut self, port: &mut SerialStream, action: TriggerAction) -> Result<()> {
+        match action {
+            TriggerAction::Send(data) => self.send_serial_data(port, &data)?,
+            TriggerAction::Bell => self.tui.bell()?,
+            TriggerAction::Capture(CaptureAction::Start) => {
+                if self.capture.is_none() {
+                    let path = self.capture_path()?;
+                    if let Err(e) = self.start_capture(&path) {
+                        self.set_status("", &format!("{:#}", e))?;
+                    }
+                }
+            }
+            TriggerAction::Capture(CaptureAction::Stop) => {
+                if self.capture.take().is_some() {
+                    self.update_status_info()?;
+                }
+            }
+            TriggerAction::Marker(text) => self.tui.print_message(&text)?,
+            TriggerAction::Dtr(dtr) => {
+                self.dtr = match dtr {
+                    DtrAction::On => true,
+                    DtrAction::Off => false,
+                    DtrAction::Toggle => !self.dtr,
+                };
+                /* Ptys and many USB adapters have no modem lines */
+                if let Err(e) = port.write_data_terminal_ready(self.dtr) {
+                    self.set_status("DTR: ", &e.to_string())?;
+                }
+            }
+            TriggerAction::Run(cmd) => {
+                if let Err(e) = trigger::run_command(&cmd) {
+                    self.set_status("", &format!("{:#}", e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_script(&mut self, path: &str) -> Result<()> {
+        self.last_script = path.to_string();
+        match Script::load(Path::new(path)) {
+            Ok(script) => {
+                self.script = Some(script);
+                self.set_status("Running script ", path)?;
+            }
+            Err(e) => self.set_status("", &format!("{:#}", e))?,
+        }
+        Ok(())
+    }
+
+    fn run_script(&mut self, port: &mut SerialStream) -> Result<()> {
+        let Some(mut script) = self.script.take() else {
+            return Ok(());
+        };
+
+        let finished = match script.run(Instant::now()) {
+            Ok((actions, finished)) => {
+                for action in actions {
+                    match action {
+                        Action::Send(data) => self.send_serial_data(port, &data)?,
+                        Action::SetBaud(baud) => {
+                            port.set_baud_rate(baud)?;
+                            self.cli.baud_rate = baud;
+                            if let Some(modbus) = self.modbus.as_mut() {
+                                modbus.set_baud_rate(baud);
+                            }
+                            self.stats.set_line(baud, bits_per_char(&self.cli));
+                            self.update_status_info()?;
+                        }
+                        Action::Log(msg) => self.tui.print_message(&msg)?,
+                    }
+                }
+                if finished {
+                    self.set_status("Script finished: ", script.name())?;
+                }
+                finished
+            }
+            Err(e) => {
+                self.set_status("Script failed: ", &format!("{:#}", e))?;
+                true
+            }
+        };
+
+        if !finished {
+            self.script = Some(script);
+        }
+        Ok(())
+    }
+
+    fn run_macros(&mut self, port: &mut SerialStream) -> Result<()> {
+        let data = self.macro_player.poll(Instant::now());
+        if !data.is_empty() {
+            self.send_serial_data(port, &data)?;
+        }
+        Ok(())
+    }
+
+    fn show_prompt(&mut self, kind: PromptKind, text: &str) -> Result<()> {
+        self.state = AppStates::Prompt(kind);
+        self.editor.set_text(text);
+        self.tui.show_prompt(kind.label(), &self.editor)
+    }
+
+    /// Hide a prompt, the line edit prompt comes back when it is active
+    fn hide_prompt(&mut self) -> Result<()> {
+        if self.opts.line_edit {
+            self.tui.show_prompt(LINE_EDIT_LABEL, &self.line_editor)
         } else {
-            self.tui.print_or_queue(&str)?;
+            self.tui.hide_prompt()
+        }
+    }
+
+    fn handle_line_edit_key(&mut self, port: &mut SerialStream, key_event: KeyEvent) -> Result<()> {
+        if key_event.code != KeyCode::Tab {
+            self.history.end_completion();
+        }
+
+        let recalled = match key_event.code {
+            KeyCode::Up => self.history.prev(self.line_editor.text()),
+            KeyCode::Down => self.history.next(),
+            KeyCode::Tab => self.history.complete(self.line_editor.text()),
+            /* Control keys the editor doesn't use go to the device, e.g. CTRL-C */
+            KeyCode::Char(c)
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) && !"aeu".contains(c) =>
+            {
+                if let Some(data) = self.encoder.encode(key_event) {
+                    self.send_serial_data(port, &data)?;
+                }
+                return Ok(());
+            }
+            _ => {
+                match self.line_editor.handle_key(key_event) {
+                    EditResult::Submit(text) => {
+                        let mut data = text.clone().into_bytes();
+                        data.extend_from_slice(self.encoder.enter.bytes());
+                        self.send_serial_data(port, &data)?;
+                        if let Err(e) = self.history.add(&text) {
+                            self.set_status("", &format!("{:#}", e))?;
+                        }
+                    }
+                    EditResult::Cancel => self.history.reset(),
+                    EditResult::None => (),
+                }
+                return self.tui.show_prompt(LINE_EDIT_LABEL, &self.line_editor);
+            }
+        };
+
+        if let Some(line) = recalled {
+            self.line_editor.set_text(line);
+            self.tui.show_prompt(LINE_EDIT_LABEL, &self.line_editor)?;
         }
         Ok(())
     }
 
-    pub fn handle_serial_event(&mut self, data: &[u8]) -> Result<()> {
-        self.print_incoming(data)?;
+    fn handle_prompt(&mut self, port: &mut SerialStream, kind: PromptKind, text: &str) -> Result<()> {
+        match kind {
+            PromptKind::RunScript => {
+                if !text.is_empty() {
+                    self.start_script(text)?;
+                }
+            }
+            PromptKind::Filter => {
+                let filter = match text {
+                    "" => Ok(None),
+                    text => LineFilter::parse(text).map(Some),
+                };
+                match filter {
+                    Ok(filter) => {
+                        self.tui.set_filter(filter)?;
+                        self.update_status_info()?;
+                    }
+                    Err(e) => self.set_status("", &format!("{:#}", e))?,
+                }
+            }
+            PromptKind::Modbus => {
+                self.last_modbus = text.to_string();
+                match modbus::build_request(text) {
+                    Ok(frame) => {
+                        self.send_serial_data(port, &frame)?;
+                        if let Some(modbus) = self.modbus.as_mut() {
+                            modbus.sent(&frame);
+                        }
+                        self.set_status("Sent: ", &format!("{:02x?}", frame))?;
+                    }
+                    Err(e) => self.set_status("", &format!("{:#}", e))?,
+                }
+            }
+            PromptKind::RxFilter => {
+                if !text.is_empty() {
+                    if let Err(e) = self.start_rx_filter(text) {
+                        self.set_status("", &format!("{:#}", e))?;
+                    }
+                }
+            }
+            PromptKind::SendHex => {
+                self.last_hex = text.to_string();
+                match parse_send_input(text) {
+                    Ok(data) if data.is_empty() => (),
+                    Ok(data) => {
+                        self.send_serial_data(port, &data)?;
+                        self.set_status("Sent: ", &format!("{:02x?}", data))?;
+                    }
+                    Err(e) => self.set_status("", &format!("{:#}", e))?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_remote_data(&mut self, port: &mut SerialStream, data: &[u8]) -> Result<()> {
+        self.send_serial_data(port, data)?;
         Ok(())
     }
 
     fn send_serial_data(&mut self, port: &mut SerialStream, data: &[u8]) -> Result<()> {
         port.write_all(data)?;
+        self.stats.sent(data);
         if self.opts.local_echo {
-            self.print_incoming(data)?;
+            let time = self.tui.now();
+            self.print_local(data, time)?;
         }
         Ok(())
     }
@@ -304,23 +1027,101 @@ impl App {
             Commands::Quit | Commands::Exit => result = AppResults::Quit,
             Commands::ToggleLocalEcho => {
                 self.opts.local_echo = !self.opts.local_echo;
-                self.tui.set_status("bla", self.opts.local_echo.val_to_str())?;
+                self.set_status("Local echo: ", self.opts.local_echo.val_to_str())?;
             },
-            Commands::ToggleLineFeed => {
-                self.opts.add_line_feed = !self.opts.add_line_feed;
-                self.tui.set_status("bla", self.opts.add_line_feed.val_to_str())?;
+            Commands::CycleRxMap => {
+                self.opts.rx_map = self.opts.rx_map.next();
+                self.set_status("RX newlines: ", self.opts.rx_map.name())?;
             },
-            Commands::ToggleCarriageReturn => {
-                self.opts.add_carriage_return = !self.opts.add_carriage_return;
-                self.tui.set_status("bla", self.opts.add_carriage_return.val_to_str())?;
+            Commands::CycleTxEnter => {
+                self.encoder.enter = self.encoder.enter.next();
+                self.set_status("Enter sends: ", self.encoder.enter.name())?;
             },
             Commands::ToggleTimestamp => {
                 self.opts.timestamp = self.opts.timestamp.next();
                 self.tui.set_prefix_timestamp(self.opts.timestamp);
-                self.tui.set_status("sdfg", self.opts.timestamp.val_to_str())?;
+                self.set_status("Timestamp: ", self.opts.timestamp.val_to_str())?;
+            },
+            Commands::ToggleBackspace => {
+                self.encoder.backspace = match self.encoder.backspace {
+                    EraseCode::Bs => EraseCode::Del,
+                    _ => EraseCode::Bs,
+                };
+                self.set_status("Backspace sends: ", self.encoder.backspace.name())?;
+                self.update_status_info()?;
+            },
+            Commands::CycleDelete => {
+                self.encoder.delete = match self.encoder.delete {
+                    EraseCode::Vt220 => EraseCode::Del,
+                    EraseCode::Del => EraseCode::Bs,
+                    EraseCode::Bs => EraseCode::Vt220,
+                };
+                self.set_status("Delete sends: ", self.encoder.delete.name())?;
+                self.update_status_info()?;
+            },
+            Commands::ToggleLineEdit => {
+                if self.tui.is_tty() {
+                    self.opts.line_edit = !self.opts.line_edit;
+                    self.hide_prompt()?;
+                    self.set_status("Line edit: ", self.opts.line_edit.val_to_str())?;
+                }
+            },
+            Commands::SendHex => {
+                if self.tui.is_tty() {
+                    let last_hex = self.last_hex.clone();
+                    self.show_prompt(PromptKind::SendHex, &last_hex)?;
+                }
+            },
+            Commands::ToggleHex => {
+                self.opts.hex = !self.opts.hex;
+                self.tui.set_hex(self.opts.hex)?;
+                self.set_status("Hex: ", self.opts.hex.val_to_str())?;
+            },
+            Commands::Filter => {
+                if self.tui.is_tty() {
+                    let text = self.tui.filter().map(|filter| filter.text().to_string());
+                    self.show_prompt(PromptKind::Filter, &text.unwrap_or_default())?;
+                }
+            },
+            Commands::ToggleCapture => {
+                if let Some((_, path)) = self.capture.take() {
+                    self.set_status("Capture stopped: ", &path)?;
+                    self.update_status_info()?;
+                } else {
+                    let path = self.capture_path()?;
+                    match self.start_capture(&path) {
+                        Ok(()) => self.set_status("Capturing to ", &path)?,
+                        Err(e) => self.set_status("", &format!("{:#}", e))?,
+                    }
+                }
+            },
+            Commands::RxFilter => {
+                if let Some(filter) = self.rx_filter.take() {
+                    self.set_status("RX filter stopped: ", filter.cmd())?;
+                    self.update_status_info()?;
+                } else if self.tui.is_tty() {
+                    let last_rx_filter = self.last_rx_filter.clone();
+                    self.show_prompt(PromptKind::RxFilter, &last_rx_filter)?;
+                }
             },
+            Commands::Modbus => {
+                if self.tui.is_tty() {
+                    let last_modbus = self.last_modbus.clone();
+                    self.show_prompt(PromptKind::Modbus, &last_modbus)?;
+                }
+            }
             Commands::ClearScreen => self.tui.clear_screen()?,
             Commands::ShowHelp => self.show_help()?,
+            Commands::ShowPlot => self.show_plot()?,
+            Commands::ShowStats => self.show_stats()?,
+            Commands::RunScript => {
+                if let Some(script) = self.script.take() {
+                    self.set_status("Script stopped: ", script.name())?;
+                } else if self.tui.is_tty() {
+                    let last_script = self.last_script.clone();
+                    self.show_prompt(PromptKind::RunScript, &last_script)?;
+                }
+            },
         }
 
         Ok(result)
@@ -337,32 +1138,31 @@ impl App {
             AppStates::Receiving => {
                 assert!(!self.tui.on_alternate_screen());
 
-                /* Check for CTRL-A */
-                if is_ctrl_a(key_event) {
+                /* Check for the escape key */
+                if self.keymap.is_escape(key_event) {
                     self.state = AppStates::CatchKey;
-                    self.tui.set_status_msg("CTRL-A Z for help")?;
-                } else if let Some(data) = key_event_to_bytes(key_event)? {
+                    let msg = self.command_keys(Commands::ShowHelp) + " for help";
+                    self.tui.set_status_msg(&msg)?;
+                } else if let Some(mac) = macros::find(&self.macros, key_event) {
+                    self.macro_player.start(mac);
+                    self.run_macros(port)?;
+                } else if self.opts.line_edit {
+                    self.handle_line_edit_key(port, key_event)?;
+                } else if let Some(data) = self.encoder.encode(key_event) {
                     self.send_serial_data(port, &data)?;
-                    // TODO: add separate option?
-                    // (currently like minicom, one option for both receiving and sending)
-                    if self.opts.add_line_feed && data[0] == b'\r' {
-                        self.send_serial_data(port, &[b'\n'])?;
-                    }
                 }
             },
             AppStates::CatchKey => {
                 /* Leave the state */
                 self.state = AppStates::Receiving;
 
-                if is_ctrl_a(key_event) {
-                    /* Got CTRL-A for the second time, send it */
-                    if let Some(data) = key_event_to_bytes(key_event)? {
+                if self.keymap.is_escape(key_event) {
+                    /* Got the escape key for the second time, send it */
+                    if let Some(data) = self.encoder.encode(key_event) {
                         self.send_serial_data(port, &data)?;
                     }
-                } else if let KeyCode::Char(c) = key_event.code {
-                    if let Some(cmd) = get_command(c) {
-                        result = self.handle_command(cmd)?;
-                    }
+                } else if let Some(cmd) = self.keymap.command(key_event) {
+                    result = self.handle_command(cmd)?;
                 } else {
                     /* Ignore other keys like 'enter' */
                 }
@@ -372,19 +1172,31 @@ impl App {
                     self.tui.hide_status()?;
                 }
             },
-            AppStates::MenuActive => {
+            AppStates::MenuActive | AppStates::PlotActive | AppStates::StatsActive => {
                 /* For now, leave the menu on any key */
                 self.tui.leave_alt()?;
                 self.state = AppStates::Receiving;
             },
+            AppStates::Prompt(kind) => {
+                match self.editor.handle_key(key_event) {
+                    EditResult::Submit(text) => {
+                        self.state = AppStates::Receiving;
+                        self.hide_prompt()?;
+                        self.handle_prompt(port, kind, &text)?;
+                    },
+                    EditResult::Cancel => {
+                        self.state = AppStates::Receiving;
+                        self.hide_prompt()?;
+                    },
+                    EditResult::None => self.tui.show_prompt(kind.label(), &self.editor)?,
+                }
+            },
         }
         Ok(result)
     }
 
     pub fn handle_resize(&mut self) -> Result<()> {
-        if self.state == AppStates::MenuActive {
-            self.tui.resize()?;
-        }
+        self.tui.resize()?;
         Ok(())
     }
 
@@ -392,75 +1204,50 @@ impl App {
         self.tui.cleanup()?;
         Ok(())
     }
-}
 
-fn key_event_to_bytes(key_event: KeyEvent) -> Result<Option<Vec<u8>>> {
-    let esc: u8 = b'\x1b';
-
-    // TODO instead of vec?
-    // let mut buf: [u8; 4] = [0; 4];
-    // let test: u32 = 0x1b;
-    // buf = u32::to_ne_bytes(test << 16 | 1);
-    // return size?
-    // or wrapped in some struct
-
-    // TODO verify against u-boot or something similar
-    let key_str: Option<Vec<u8>> = match key_event.code {
-        KeyCode::Backspace => Some(Vec::from([b'\x08'])),
-        KeyCode::Enter => Some(Vec::from([b'\r'])),
-        KeyCode::Left => Some(Vec::from([esc, b'\x5b', b'\x44'])),
-        KeyCode::Right => Some(Vec::from([esc, b'\x5b', b'\x43'])),
-        KeyCode::Up => Some(Vec::from([esc, b'\x5b', b'\x41'])),
-        KeyCode::Down => Some(Vec::from([esc, b'\x5b', b'\x42'])),
-        KeyCode::Home => Some(Vec::from([esc, b'\x5b', b'\x48'])),
-        KeyCode::End => Some(Vec::from([esc, b'\x5b', b'\x46'])),
-        KeyCode::PageUp => Some(Vec::from([esc, b'\x5b', b'\x35', b'\x7e'])),
-        KeyCode::PageDown => Some(Vec::from([esc, b'\x5b', b'\x36', b'\x7e'])),
-        KeyCode::Tab => Some(Vec::from([b'\t'])),
-        KeyCode::BackTab => Some(Vec::from([esc, b'\x5b', b'\x5a'])),
-        KeyCode::Delete => Some(Vec::from([esc, b'\x5b', b'\x33', b'\x7e'])),
-        KeyCode::Insert => Some(Vec::from([esc, b'\x5b', b'\x32', b'\x7e'])),
-        KeyCode::F(num) => {
-            if num <= 4 {
-                Some(Vec::from([esc, b'\x5b', b'\x4f', b'\x49' + num]))
-            } else {
-                None
-            }
-        }
-        KeyCode::Char(ch) => {
-            if is_ctrl_key(key_event) && ch.is_ascii_lowercase() {
-                Some(Vec::from([ch as u8 - b'a']))
-            } else {
-                Some(Vec::from([ch as u8]))
-            }
-        }
-        KeyCode::Null => Some(Vec::from([b'\0'])),
-        KeyCode::Esc => Some(Vec::from([esc])),
-        KeyCode::CapsLock => None,
-        KeyCode::ScrollLock => None,
-        KeyCode::NumLock => None,
-        KeyCode::PrintScreen => None,
-        KeyCode::Pause => None,
-        KeyCode::Menu => None,
-        KeyCode::KeypadBegin => None,
-        KeyCode::Media(_) => None,
-        KeyCode::Modifier(_) => None,
-    };
-    Ok(key_str)
+    /// Summary of the session counters, for stderr on exit
+    pub fn stats_summary(&mut self, port: &SerialStream) -> String {
+        self.stats.summary(port)
+    }
 }
 
-fn is_ctrl_key(key_event: KeyEvent) -> bool {
-    if key_event.modifiers & KeyModifiers::CONTROL == KeyModifiers::CONTROL {
-        return true;
-    }
-    false
+/* Start, data, parity and stop bits of every character on the line */
+pub fn bits_per_char(cli: &Cli) -> u32 {
+    let data_bits = match cli.data_bits {
+        tokio_serial::DataBits::Five => 5,
+        tokio_serial::DataBits::Six => 6,
+        tokio_serial::DataBits::Seven => 7,
+        tokio_serial::DataBits::Eight => 8,
+    };
+    let parity_bits = match cli.parity {
+        tokio_serial::Parity::None => 0,
+        _ => 1,
+    };
+    let stop_bits = match cli.stop_bits {
+        tokio_serial::StopBits::One => 1,
+        tokio_serial::StopBits::Two => 2,
+    };
+    1 + data_bits + parity_bits + stop_bits
 }
 
-fn is_ctrl_a(key_event: KeyEvent) -> bool {
-    if let KeyCode::Char(c) = key_event.code {
-        if c == 'a' && key_event.modifiers & KeyModifiers::CONTROL == KeyModifiers::CONTROL {
-            return true;
+/// Input of the send hex prompt: "01 03 00 0A" or an escaped string like
+/// "\x1b[6n", optionally followed by a checksum to append, e.g. " +crc16".
+fn parse_send_input(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim();
+    let (text, checksum) = match text.rsplit_once(" +") {
+        Some((data, name)) if !name.contains(|c: char| c.is_whitespace() || c == '"') => {
+            (data.trim_end(), Some(Checksum::parse(name)?))
         }
+        _ => (text, None),
+    };
+
+    let mut data = match text.strip_prefix('"').and_then(|str| str.strip_suffix('"')) {
+        Some(str) => unescape(str)?,
+        None if text.contains('\\') => unescape(text)?,
+        None => parse_hex(text)?,
+    };
+    if let Some(checksum) = checksum {
+        data.extend(checksum.compute(&data));
     }
-    false
+    Ok(data)
 }
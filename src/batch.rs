@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::{process::ExitCode, thread};
+use tokio::{
+    io::{stdout, AsyncReadExt, AsyncWriteExt},
+    select,
+    sync::mpsc,
+    time::{sleep_until, Duration, Instant},
+};
+use tokio_serial::SerialStream;
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(windows)]
+use tokio::signal::windows::ctrl_close;
+
+/// Exit codes of batch mode, so scripts can tell what happened
+pub const EXIT_STDIN_EOF: u8 = 0;
+pub const EXIT_PORT_ERROR: u8 = 2;
+pub const EXIT_STDOUT_CLOSED: u8 = 3;
+pub const EXIT_TERMINATED: u8 = 143;
+
+/// Headless mode: stdin goes to the port, the port goes to stdout.
+/// No terminal handling, no banner and no escape key, bytes are passed as is.
+pub async fn run(port: &mut SerialStream, drain: Duration) -> Result<ExitCode> {
+    let mut port_buf: [u8; 128] = [0; 128];
+    let mut stdin = spawn_stdin_reader();
+    let mut stdout = stdout();
+
+    /* Set once stdin is closed, we stop after draining the port */
+    let mut deadline: Option<Instant> = None;
+
+    #[cfg(unix)]
+    let mut sig_term = signal(SignalKind::terminate())?;
+    #[cfg(windows)]
+    let mut sig_term = ctrl_close()?;
+
+    loop {
+        select! {
+            /* Serial input */
+            read_bytes = port.read(&mut port_buf) => {
+                match read_bytes {
+                    Ok(0) | Err(_) => return Ok(ExitCode::from(EXIT_PORT_ERROR)),
+                    Ok(n) => {
+                        let written = stdout.write_all(&port_buf[0..n]).await;
+                        if written.and(stdout.flush().await).is_err() {
+                            return Ok(ExitCode::from(EXIT_STDOUT_CLOSED));
+                        }
+                    }
+                }
+            }
+
+            /* Stdin, until it is closed */
+            data = stdin.recv(), if deadline.is_none() => {
+                match data {
+                    None => deadline = Some(Instant::now() + drain),
+                    Some(data) => {
+                        if port.write_all(&data).await.is_err() {
+                            return Ok(ExitCode::from(EXIT_PORT_ERROR));
+                        }
+                    }
+                }
+            }
+
+            /* Done draining */
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                return Ok(ExitCode::from(EXIT_STDIN_EOF));
+            }
+
+            _ = sig_term.recv() => {
+                return Ok(ExitCode::from(EXIT_TERMINATED));
+            }
+        }
+    }
+}
+
+/// Read stdin on a plain thread, tokio's stdin can't be cancelled and would
+/// keep the runtime from shutting down when we exit before stdin is closed.
+fn spawn_stdin_reader() -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel(32);
+    thread::spawn(move || {
+        let mut buf: [u8; 128] = [0; 128];
+        let mut stdin = std::io::stdin().lock();
+        loop {
+            match std::io::Read::read(&mut stdin, &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.blocking_send(buf[0..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        /* Dropping tx signals EOF */
+    });
+    rx
+}
@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+
+/// Checksums that can be appended to data sent by hand, or checked on
+/// received frames
+#[derive(Clone, Copy, PartialEq)]
+pub enum Checksum {
+    /// CRC-16/MODBUS, low byte first
+    Crc16,
+    /// CRC-8/SMBUS, polynomial 0x07
+    Crc8,
+    /// Sum of all bytes, modulo 256
+    Sum8,
+    /// CRC-16/X-25, the HDLC frame check sequence, low byte first
+    Fcs16,
+}
+
+impl Checksum {
+    pub fn parse(name: &str) -> Result<Checksum> {
+        match name.to_ascii_lowercase().as_str() {
+            "crc16" => Ok(Checksum::Crc16),
+            "crc8" => Ok(Checksum::Crc8),
+            "sum8" => Ok(Checksum::Sum8),
+            "fcs16" => Ok(Checksum::Fcs16),
+            _ => bail!("Unknown checksum '{}' (use crc16, crc8, sum8 or fcs16)", name),
+        }
+    }
+
+    /// The checksum bytes, in the order they are sent
+    pub fn compute(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Checksum::Crc16 => crc16_modbus(data).to_le_bytes().to_vec(),
+            Checksum::Crc8 => vec![crc8(data)],
+            Checksum::Sum8 => vec![data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))],
+            Checksum::Fcs16 => fcs16(data).to_le_bytes().to_vec(),
+        }
+    }
+
+    /// Length of the checksum in bytes
+    pub fn size(&self) -> usize {
+        match *self {
+            Checksum::Crc16 | Checksum::Fcs16 => 2,
+            Checksum::Crc8 | Checksum::Sum8 => 1,
+        }
+    }
+}
+
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xa001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+pub fn fcs16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::Checksum;
+
+    #[test]
+    fn check_values() {
+        /* Check values from the CRC catalogue */
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), [0x37, 0x4b]);
+        assert_eq!(Checksum::Crc8.compute(b"123456789"), [0xf4]);
+        assert_eq!(Checksum::Sum8.compute(b"123456789"), [0xdd]);
+        assert_eq!(Checksum::Fcs16.compute(b"123456789"), [0x6e, 0x90]);
+
+        /* Modbus read holding registers request */
+        let request = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0a];
+        assert_eq!(Checksum::Crc16.compute(&request), [0xc5, 0xcd]);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::highlight::HighlightRule;
+use crate::keys::EraseCode;
+use crate::newline::{RxMap, TxEnter};
+use crate::plot::PlotConfig;
+use crate::trigger::TriggerConfig;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+/* Example config.toml:
+ *
+ *   escape_key = "ctrl-t"
+ *   backspace = "del"           bs, del or vt220
+ *   delete = "vt220"
+ *   rx_map = "crcrlf"           none, crcrlf, lfcrlf, crlf or igncr
+ *   tx_enter = "crlf"           cr, lf or crlf
+ *
+ *   [keys]
+ *   quit = "q"
+ *   clear_screen = "l"
+ *   show_help = "h"
+ *
+ *   [macros]
+ *   F5 = 'mmc dev 1\r{sleep 500}mmc info\r'
+ */
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Key that starts a command, CTRL-A when not set
+    pub escape_key: Option<String>,
+    /// What the Backspace key sends, BS when not set
+    pub backspace: Option<EraseCode>,
+    /// What the Delete key sends, VT220 when not set
+    pub delete: Option<EraseCode>,
+    /// Newline mapping of received data, none when not set
+    pub rx_map: Option<RxMap>,
+    /// What the Enter key sends, CR when not set
+    pub tx_enter: Option<TxEnter>,
+    /// Command name to key, overrides the default key of that command ("none" unbinds it)
+    pub keys: BTreeMap<String, String>,
+    /// Function key to the string it sends, see macros.rs
+    pub macros: BTreeMap<String, String>,
+    /// Colors for received text, see highlight.rs (defaults when not set)
+    pub highlight: Option<Vec<HighlightRule>>,
+    /// Actions on received text, see trigger.rs
+    pub trigger: Vec<TriggerConfig>,
+    /// Numbers to plot, see plot.rs
+    pub plot: Option<PlotConfig>,
+}
+
+fn default_path() -> Option<PathBuf> {
+    #[cfg(unix)]
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    #[cfg(windows)]
+    let dir = env::var_os("APPDATA").map(PathBuf::from);
+
+    dir.map(|dir| dir.join("minircom").join("config.toml"))
+}
+
+impl Config {
+    /// Load the given config file, or the default one when it exists
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let str = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read config {}", path.display()))?;
+        toml::from_str(&str).with_context(|| format!("Invalid config {}", path.display()))
+    }
+}
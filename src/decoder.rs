@@ -0,0 +1,193 @@
+use std::fmt::Write;
+use time::OffsetDateTime;
+
+/// Part of a line, ready to print
+pub struct Segment {
+    /// When the first byte of the line arrived, set when the segment starts a line
+    pub start: Option<OffsetDateTime>,
+    pub text: String,
+}
+
+/// Lines without an end are dealt with after this many bytes, by the filter,
+/// the highlighter and the plot
+pub const MAX_LINE: usize = 4096;
+
+/* Bytes per row in hex mode */
+const HEX_ROW: usize = 16;
+
+/// Turns received bytes into text split at line ends, or into rows of hex.
+/// Multi-byte characters split over reads are kept until they are complete.
+pub struct LineDecoder {
+    hex: bool,
+    /* Bytes on the current row in hex mode */
+    hex_col: usize,
+    on_newline: bool,
+    /* Arrival of the first byte of the current line, while it isn't printed yet */
+    line_start: Option<OffsetDateTime>,
+    /* Start of a UTF-8 sequence that isn't complete yet */
+    pending: Vec<u8>,
+}
+
+impl Default for LineDecoder {
+    fn default() -> Self {
+        LineDecoder {
+            hex: false,
+            hex_col: 0,
+            on_newline: true,
+            line_start: None,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl LineDecoder {
+    pub fn set_hex(&mut self, hex: bool) {
+        self.hex = hex;
+    }
+
+    /// End the current line, if it isn't already
+    pub fn break_line(&mut self) -> Option<Segment> {
+        if self.on_newline {
+            return None;
+        }
+        self.on_newline = true;
+        self.hex_col = 0;
+        Some(Segment {
+            start: None,
+            text: "\r\n".to_string(),
+        })
+    }
+
+    /// Decode `data` that arrived at `time`
+    pub fn decode(&mut self, data: &[u8], time: OffsetDateTime) -> Vec<Segment> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        if self.on_newline && self.line_start.is_none() {
+            self.line_start = Some(time);
+        }
+
+        if self.hex {
+            let mut bytes = std::mem::take(&mut self.pending);
+            bytes.extend_from_slice(data);
+            return self.decode_hex(&bytes, time);
+        }
+        self.decode_text(data, time)
+    }
+
+    /// Decode as text, also in hex mode (for local messages)
+    pub fn decode_text(&mut self, data: &[u8], time: OffsetDateTime) -> Vec<Segment> {
+        if self.on_newline && self.line_start.is_none() {
+            self.line_start = Some(time);
+        }
+        self.hex_col = 0;
+
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(data);
+
+        let mut text = String::with_capacity(bytes.len());
+        let mut rest = &bytes[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(str) => {
+                    text.push_str(str);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            self.pending = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut segments = Vec::new();
+        for line in text.split_inclusive('\n') {
+            let start = match self.on_newline {
+                true => Some(self.line_start.take().unwrap_or(time)),
+                false => None,
+            };
+            self.on_newline = line.ends_with('\n');
+            segments.push(Segment {
+                start,
+                text: line.to_string(),
+            });
+        }
+        segments
+    }
+
+    fn decode_hex(&mut self, bytes: &[u8], time: OffsetDateTime) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut segment = Segment {
+            start: None,
+            text: String::new(),
+        };
+
+        for byte in bytes {
+            if self.on_newline {
+                if !segment.text.is_empty() {
+                    segments.push(segment);
+                }
+                segment = Segment {
+                    start: Some(self.line_start.take().unwrap_or(time)),
+                    text: String::new(),
+                };
+                self.on_newline = false;
+            }
+
+            write!(segment.text, "{:02x} ", byte).unwrap();
+            self.hex_col += 1;
+            if self.hex_col == HEX_ROW {
+                segment.text.push_str("\r\n");
+                self.hex_col = 0;
+                self.on_newline = true;
+            }
+        }
+        if !segment.text.is_empty() {
+            segments.push(segment);
+        }
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::LineDecoder;
+    use time::{Duration, OffsetDateTime};
+
+    #[test]
+    fn lines_and_utf8() {
+        let mut decoder = LineDecoder::default();
+        let t0 = OffsetDateTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::milliseconds(5);
+        let t2 = t0 + Duration::milliseconds(12);
+
+        /* "é" split over two reads */
+        let segments = decoder.decode(b"boot\nstage \xc3", t0);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].text, "boot\n");
+        assert_eq!(segments[0].start, Some(t0));
+        assert_eq!(segments[1].text, "stage ");
+        assert_eq!(segments[1].start, Some(t0));
+
+        let segments = decoder.decode(b"\xa9\n", t1);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].text, "é\n");
+        assert_eq!(segments[0].start, None);
+
+        /* A line that starts with an incomplete character gets the time of its first byte */
+        assert!(decoder.decode(b"\xe2\x82", t1).is_empty());
+        let segments = decoder.decode(b"\xac\xff", t2);
+        assert_eq!(segments[0].text, "€\u{fffd}");
+        assert_eq!(segments[0].start, Some(t1));
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{bail, Context, Result};
+use defmt_decoder::{DecodeError, Frame, Locations, StreamDecoder, Table};
+use std::fmt::Write;
+use std::path::Path;
+
+/* Give up on data without frame ends after this many bytes */
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Decodes defmt log frames with the table from the firmware ELF
+pub struct DefmtDecoder {
+    table: &'static Table,
+    decoder: Box<dyn StreamDecoder + Send + Sync>,
+    locations: Locations,
+    pending: usize,
+}
+
+fn format_frame(frame: &Frame, locations: &Locations) -> String {
+    let mut line = String::new();
+    if let Some(timestamp) = frame.display_timestamp() {
+        write!(line, "{} ", timestamp).unwrap();
+    }
+    if let Some(level) = frame.level() {
+        write!(line, "{:<5} ", level.as_str().to_uppercase()).unwrap();
+    }
+    write!(line, "{}", frame.display_message()).unwrap();
+    if let Some(location) = locations.get(&frame.index()) {
+        write!(line, "  @ {}:{}", location.file.display(), location.line).unwrap();
+    }
+    line.push_str("\r\n");
+    line
+}
+
+impl DefmtDecoder {
+    pub fn open(path: &Path) -> Result<DefmtDecoder> {
+        let elf = std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+        let table = Table::parse(&elf)
+            .with_context(|| format!("Invalid defmt data in {}", path.display()))?
+            .with_context(|| format!("No defmt data in {}", path.display()))?;
+        if !table.encoding().can_recover() {
+            bail!("Only rzcobs encoded defmt is supported");
+        }
+
+        /* Locations need debug info, without it there are just none */
+        let locations = table.get_locations(&elf).unwrap_or_default();
+
+        /* The table is needed as long as we decode, which is until exit */
+        let table: &'static Table = Box::leak(Box::new(table));
+        Ok(DefmtDecoder {
+            table,
+            decoder: table.new_stream_decoder(),
+            locations,
+            pending: 0,
+        })
+    }
+
+    /// Received data, returns the complete log lines in it
+    pub fn decode(&mut self, data: &[u8]) -> String {
+        self.decoder.received(data);
+        self.pending += data.len();
+
+        let mut lines = String::new();
+        loop {
+            match self.decoder.decode() {
+                Ok(frame) => {
+                    lines += &format_frame(&frame, &self.locations);
+                    self.pending = 0;
+                }
+                Err(DecodeError::Malformed) => {
+                    lines += "(malformed defmt frame)\r\n";
+                    self.pending = 0;
+                }
+                Err(DecodeError::UnexpectedEof) => break,
+            }
+        }
+
+        /* Probably not defmt at all, start over so the buffer doesn't grow forever */
+        if self.pending > MAX_PENDING {
+            self.decoder = self.table.new_stream_decoder();
+            self.pending = 0;
+        }
+        lines
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{bail, Result};
+
+/// Convert a user supplied string with C-style escapes (`\r`, `\x03`, ...) to bytes
+pub fn unescape(str: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(str.len());
+    let mut chars = str.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf: [u8; 4] = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(b'\0'),
+            Some('a') => bytes.push(b'\x07'),
+            Some('b') => bytes.push(b'\x08'),
+            Some('e') => bytes.push(b'\x1b'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('\'') => bytes.push(b'\''),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                /* from_str_radix also takes a sign */
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => bytes.push(byte),
+                    _ => bail!("Invalid escape \\x{} (expected two hex digits)", hex),
+                }
+            }
+            Some(c) => bail!("Unknown escape \\{}", c),
+            None => bail!("Trailing \\ in \"{}\"", str),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parse hex bytes like "01 03 00 0A", "0103000a" or "0x01,0x03"
+pub fn parse_hex(str: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for token in str.split(|c: char| c.is_whitespace() || c == ',') {
+        let digits = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+        let prefixed = digits.len() != token.len();
+        if digits.len() % 2 != 0
+            || (prefixed && digits.is_empty())
+            || !digits.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            bail!("Invalid hex '{}'", token);
+        }
+        for pos in (0..digits.len()).step_by(2) {
+            bytes.push(u8::from_str_radix(&digits[pos..pos + 2], 16)?);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::escape::{parse_hex, unescape};
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape(r"setenv\r\n\t\0").unwrap(), b"setenv\r\n\t\0");
+        assert_eq!(unescape(r#"\a\b\e\\\"\'"#).unwrap(), b"\x07\x08\x1b\\\"'");
+        assert_eq!(unescape(r"\x03\xFFé").unwrap(), b"\x03\xff\xc3\xa9");
+
+        let error = |str: &str| unescape(str).unwrap_err().to_string();
+        assert_eq!(error(r"\x3"), r"Invalid escape \x3 (expected two hex digits)");
+        assert_eq!(error(r"\xg0"), r"Invalid escape \xg0 (expected two hex digits)");
+        assert_eq!(error(r"\x+1"), r"Invalid escape \x+1 (expected two hex digits)");
+        assert_eq!(error(r"\q"), r"Unknown escape \q");
+        assert_eq!(error(r"ab\"), r#"Trailing \ in "ab\""#);
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex("01 03 00 0A").unwrap(), [0x01, 0x03, 0x00, 0x0a]);
+        assert_eq!(parse_hex("0103000a").unwrap(), [0x01, 0x03, 0x00, 0x0a]);
+        assert_eq!(parse_hex("0x01, 0X03,ff").unwrap(), [0x01, 0x03, 0xff]);
+        assert!(parse_hex("  ").unwrap().is_empty());
+
+        let error = |str: &str| parse_hex(str).unwrap_err().to_string();
+        assert_eq!(error("01 3"), "Invalid hex '3'");
+        assert_eq!(error("0g"), "Invalid hex '0g'");
+        assert_eq!(error("0x"), "Invalid hex '0x'");
+        assert_eq!(error("+1"), "Invalid hex '+1'");
+    }
+}
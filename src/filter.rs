@@ -0,0 +1,139 @@
+use crate::decoder::{Segment, MAX_LINE};
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Shows only the lines that match a regex, or hides them when the filter
+/// starts with '!'. Lines are held back until they are complete, or until
+/// `flush` when the device went quiet.
+pub struct LineFilter {
+    text: String,
+    regex: Regex,
+    exclude: bool,
+    pending: Vec<Segment>,
+    pending_len: usize,
+    /* Whether the rest of a line decided on before its end passes */
+    decided: Option<bool>,
+}
+
+impl LineFilter {
+    pub fn parse(text: &str) -> Result<LineFilter> {
+        let (exclude, pattern) = match text.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, text),
+        };
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid filter '{}'", pattern))?;
+
+        Ok(LineFilter {
+            text: text.to_string(),
+            regex,
+            exclude,
+            pending: Vec::new(),
+            pending_len: 0,
+            decided: None,
+        })
+    }
+
+    /// As entered, e.g. "!debug"
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The segments of the lines that pass, complete lines only
+    pub fn filter(&mut self, segments: Vec<Segment>) -> Vec<Segment> {
+        let mut passed = Vec::new();
+
+        for segment in segments {
+            let ends_line = segment.text.ends_with('\n');
+            if let Some(pass) = self.decided {
+                if ends_line {
+                    self.decided = None;
+                }
+                if pass {
+                    passed.push(segment);
+                }
+                continue;
+            }
+            self.pending_len += segment.text.len();
+            self.pending.push(segment);
+            if ends_line || self.pending_len > MAX_LINE {
+                passed.extend(self.decide(ends_line));
+            }
+        }
+        passed
+    }
+
+    /// Decide on what was received of the current line, the rest of it
+    /// follows that decision
+    pub fn flush(&mut self) -> Vec<Segment> {
+        self.decide(false)
+    }
+
+    fn decide(&mut self, ends_line: bool) -> Vec<Segment> {
+        let segments = std::mem::take(&mut self.pending);
+        self.pending_len = 0;
+
+        let line: String = segments.iter().map(|segment| segment.text.as_str()).collect();
+        if line.is_empty() {
+            return Vec::new();
+        }
+        let pass = self.regex.is_match(&line) != self.exclude;
+        if !ends_line {
+            self.decided = Some(pass);
+        }
+        match pass {
+            true => segments,
+            false => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::{Segment, MAX_LINE};
+    use crate::filter::LineFilter;
+
+    fn filter(filter: &mut LineFilter, texts: &[&str]) -> Vec<String> {
+        let segments = texts.iter().map(|text| Segment {
+            start: None,
+            text: text.to_string(),
+        });
+        filter.filter(segments.collect()).into_iter().map(|segment| segment.text).collect()
+    }
+
+    #[test]
+    fn lines() {
+        let mut include = LineFilter::parse("err").unwrap();
+        assert_eq!(filter(&mut include, &["ok\r\n", "an e"]), Vec::<String>::new());
+        /* Parts of a line pass together once it is complete */
+        assert_eq!(filter(&mut include, &["rror\r\n", "fine\n"]), ["an e", "rror\r\n"]);
+
+        let mut exclude = LineFilter::parse("!debug").unwrap();
+        assert_eq!(exclude.text(), "!debug");
+        assert_eq!(filter(&mut exclude, &["debug: x\n", "boot\n", "=> "]), ["boot\n"]);
+        /* The prompt shows when the device goes quiet */
+        assert_eq!(exclude.flush().len(), 1);
+        assert!(exclude.flush().is_empty());
+
+        /* Endless lines are decided on anyway */
+        let mut exclude = LineFilter::parse("!debug").unwrap();
+        let long = "x".repeat(MAX_LINE + 1);
+        assert_eq!(filter(&mut exclude, &[&long]), [long.as_str()]);
+        assert_eq!(filter(&mut exclude, &["debug\n"]), ["debug\n"]);
+
+        assert!(LineFilter::parse("(").is_err());
+    }
+
+    #[test]
+    fn rest_of_flushed_line() {
+        let mut include = LineFilter::parse("wifi").unwrap();
+        assert!(filter(&mut include, &["[wifi] scanning..."]).is_empty());
+        assert_eq!(include.flush().len(), 1);
+        assert_eq!(filter(&mut include, &["done\n", "[eth] up\n"]), ["done\n"]);
+        assert_eq!(filter(&mut include, &["[wifi] ok\n"]), ["[wifi] ok\n"]);
+
+        let mut exclude = LineFilter::parse("!debug").unwrap();
+        assert!(filter(&mut exclude, &["debug: x"]).is_empty());
+        assert!(exclude.flush().is_empty());
+        assert_eq!(filter(&mut exclude, &[" y\n", "boot\n"]), ["boot\n"]);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::checksum::Checksum;
+use clap::ValueEnum;
+use std::fmt::Write;
+
+/* Give up on a frame without an end after this many bytes */
+const MAX_FRAME: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Framing {
+    /// SLIP (RFC 1055), frames end with 0xc0
+    Slip,
+    /// COBS, frames end with 0x00
+    Cobs,
+    /// HDLC-like, frames between 0x7e flags with 0x7d escapes
+    Hdlc,
+}
+
+/// A received frame, unpacked as far as possible
+struct Frame {
+    data: Vec<u8>,
+    error: Option<&'static str>,
+}
+
+/// Finds the frames in the received bytes
+trait Deframer {
+    /// The next byte, returns the frame it ends
+    fn push(&mut self, byte: u8) -> Option<Frame>;
+}
+
+/* Bytes of the current frame and whether something was wrong with it */
+#[derive(Default)]
+struct FrameBuffer {
+    data: Vec<u8>,
+    escaped: bool,
+    error: Option<&'static str>,
+}
+
+impl FrameBuffer {
+    fn add(&mut self, byte: u8) -> Option<Frame> {
+        self.data.push(byte);
+        if self.data.len() < MAX_FRAME {
+            return None;
+        }
+        self.error = Some("no frame end");
+        self.take()
+    }
+
+    fn take(&mut self) -> Option<Frame> {
+        let frame = Frame {
+            data: std::mem::take(&mut self.data),
+            error: self.error.take(),
+        };
+        self.escaped = false;
+        Some(frame)
+    }
+}
+
+#[derive(Default)]
+struct Slip(FrameBuffer);
+
+impl Deframer for Slip {
+    fn push(&mut self, byte: u8) -> Option<Frame> {
+        let buf = &mut self.0;
+        if buf.escaped {
+            buf.escaped = false;
+            return match byte {
+                0xdc => buf.add(0xc0),
+                0xdd => buf.add(0xdb),
+                _ => {
+                    buf.error = Some("bad escape");
+                    buf.add(byte)
+                }
+            };
+        }
+        match byte {
+            /* Senders often start with an END too, there are no empty frames */
+            0xc0 if buf.data.is_empty() => None,
+            0xc0 => buf.take(),
+            0xdb => {
+                buf.escaped = true;
+                None
+            }
+            _ => buf.add(byte),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Cobs(FrameBuffer);
+
+fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut pos = 0;
+    while pos < data.len() {
+        let code = data[pos] as usize;
+        if code == 0 || pos + code > data.len() {
+            return None;
+        }
+        decoded.extend_from_slice(&data[pos + 1..pos + code]);
+        pos += code;
+        if code < 0xff && pos < data.len() {
+            decoded.push(0);
+        }
+    }
+    Some(decoded)
+}
+
+impl Deframer for Cobs {
+    fn push(&mut self, byte: u8) -> Option<Frame> {
+        if byte != 0 {
+            return self.0.add(byte);
+        }
+        if self.0.data.is_empty() {
+            return None;
+        }
+
+        let mut frame = self.0.take()?;
+        match cobs_decode(&frame.data) {
+            Some(decoded) => frame.data = decoded,
+            None => frame.error = Some("COBS error"),
+        }
+        Some(frame)
+    }
+}
+
+#[derive(Default)]
+struct Hdlc(FrameBuffer);
+
+impl Deframer for Hdlc {
+    fn push(&mut self, byte: u8) -> Option<Frame> {
+        let buf = &mut self.0;
+        match byte {
+            /* ESC followed by a flag aborts the frame */
+            0x7e if buf.escaped => {
+                buf.error = Some("aborted");
+                buf.take()
+            }
+            /* Back to back frames share a flag */
+            0x7e if buf.data.is_empty() => None,
+            0x7e => buf.take(),
+            0x7d if !buf.escaped => {
+                buf.escaped = true;
+                None
+            }
+            _ if buf.escaped => {
+                buf.escaped = false;
+                buf.add(byte ^ 0x20)
+            }
+            _ => buf.add(byte),
+        }
+    }
+}
+
+/// Shows every received frame as a line of hex, with its length and checksum
+/// status
+pub struct FrameDecoder {
+    deframer: Box<dyn Deframer>,
+    check: Option<Checksum>,
+}
+
+impl FrameDecoder {
+    /// `check` is the checksum at the end of every frame
+    pub fn new(framing: Framing, check: Option<Checksum>) -> FrameDecoder {
+        let deframer: Box<dyn Deframer> = match framing {
+            Framing::Slip => Box::<Slip>::default(),
+            Framing::Cobs => Box::<Cobs>::default(),
+            Framing::Hdlc => Box::<Hdlc>::default(),
+        };
+        FrameDecoder { deframer, check }
+    }
+
+    /// Received data, returns a line for every frame that ended in it
+    pub fn decode(&mut self, data: &[u8]) -> String {
+        let mut lines = String::new();
+        for &byte in data {
+            if let Some(frame) = self.deframer.push(byte) {
+                lines += &self.format_frame(&frame);
+            }
+        }
+        lines
+    }
+
+    fn format_frame(&self, frame: &Frame) -> String {
+        let mut line = String::new();
+        for byte in &frame.data {
+            write!(line, "{:02x} ", byte).unwrap();
+        }
+        write!(line, " ({} bytes", frame.data.len()).unwrap();
+
+        if let Some(error) = frame.error {
+            write!(line, ", {}", error).unwrap();
+        } else if let Some(check) = self.check {
+            match frame.data.len().checked_sub(check.size()) {
+                Some(len) => {
+                    let (payload, received) = frame.data.split_at(len);
+                    let expected = check.compute(payload);
+                    if expected == received {
+                        line += ", CRC ok";
+                    } else {
+                        line += ", CRC fail, expected";
+                        for byte in expected {
+                            write!(line, " {:02x}", byte).unwrap();
+                        }
+                    }
+                }
+                None => line += ", too short for CRC",
+            }
+        }
+        line += ")\r\n";
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::Checksum;
+    use crate::framing::{FrameDecoder, Framing};
+
+    #[test]
+    fn frames() {
+        let mut slip = FrameDecoder::new(Framing::Slip, None);
+        assert_eq!(slip.decode(b"\xc0\x01\xdb\xdc\xdb"), "");
+        assert_eq!(slip.decode(b"\xdd\xc0"), "01 c0 db  (3 bytes)\r\n");
+
+        /* 11 22 00 33 with its CRC-16/MODBUS */
+        let mut cobs = FrameDecoder::new(Framing::Cobs, Some(Checksum::Crc16));
+        assert_eq!(
+            cobs.decode(b"\x03\x11\x22\x04\x33\xe5\x07\x00"),
+            "11 22 00 33 e5 07  (6 bytes, CRC ok)\r\n"
+        );
+        assert_eq!(
+            cobs.decode(b"\x03\x11\x22\x04\x33\xe5\x08\x00\x05\x01\x00"),
+            "11 22 00 33 e5 08  (6 bytes, CRC fail, expected e5 07)\r\n05 01  (2 bytes, COBS error)\r\n"
+        );
+
+        let mut hdlc = FrameDecoder::new(Framing::Hdlc, Some(Checksum::Fcs16));
+        assert_eq!(
+            hdlc.decode(b"\x7e\x03\x7d\x5e\xd6\xbf\x7e\x7e\x01\x7d\x7e"),
+            "03 7e d6 bf  (4 bytes, CRC ok)\r\n01  (1 bytes, aborted)\r\n"
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use crate::decoder::MAX_LINE;
+use anyhow::{anyhow, Context, Result};
+use crossterm::style::{Attribute, Color, ContentStyle};
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt::Write;
+
+/* Rules in the config file, the first matching rule wins:
+ *
+ *   [[highlight]]
+ *   regex = '(?i)\btimeout\b'
+ *   fg = "magenta"              crossterm color names, 0-255 or #rrggbb
+ *   bg = "dark_grey"
+ *   bold = true
+ *
+ * Without any [[highlight]] the defaults below are used, `highlight = []` turns them off. */
+
+/* Limit on the SGR sequences kept, for devices that never reset */
+const MAX_SGR: usize = 1024;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightRule {
+    regex: String,
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    bold: bool,
+}
+
+fn default_rules() -> Vec<HighlightRule> {
+    let rule = |regex: &str, fg: &str| HighlightRule {
+        regex: regex.to_string(),
+        fg: Some(fg.to_string()),
+        bg: None,
+        bold: true,
+    };
+    vec![rule(r"(?i)error|fail|panic", "red"), rule(r"(?i)warn", "yellow")]
+}
+
+fn parse_color(str: &str) -> Result<Color> {
+    if let Ok(color) = Color::try_from(str) {
+        return Ok(color);
+    }
+    if let Ok(value) = str.parse::<u8>() {
+        return Ok(Color::AnsiValue(value));
+    }
+    match str.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 => {
+            let rgb = u32::from_str_radix(hex, 16).map_err(|_| anyhow!("Invalid color '{}'", str))?;
+            Ok(Color::Rgb {
+                r: (rgb >> 16) as u8,
+                g: (rgb >> 8) as u8,
+                b: rgb as u8,
+            })
+        }
+        _ => Err(anyhow!("Unknown color '{}'", str)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    /* After ESC */
+    Start,
+    /* Control sequence, ESC [ */
+    Csi,
+    /* Operating system command, ESC ], up to BEL or ESC \ */
+    Osc,
+    OscEsc,
+}
+
+/// Colors the parts of received lines that match the rules
+pub struct Highlighter {
+    rules: Vec<(Regex, ContentStyle)>,
+    /* What was printed of the current line so far, matches may start in it */
+    line: String,
+    escape: Escape,
+    csi: String,
+    /* The SGR sequences of the device since its last reset, printed again
+     * after a highlight so its own colors continue */
+    sgr: String,
+}
+
+impl Highlighter {
+    pub fn from_config(rules: Option<&[HighlightRule]>) -> Result<Highlighter> {
+        let defaults;
+        let rules = match rules {
+            Some(rules) => rules,
+            None => {
+                defaults = default_rules();
+                &defaults
+            }
+        };
+
+        let mut parsed = Vec::new();
+        for rule in rules {
+            let regex = Regex::new(&rule.regex)
+                .with_context(|| format!("Invalid highlight regex '{}'", rule.regex))?;
+            let mut style = ContentStyle::new();
+            style.foreground_color = rule.fg.as_deref().map(parse_color).transpose()?;
+            style.background_color = rule.bg.as_deref().map(parse_color).transpose()?;
+            if rule.bold {
+                style.attributes.set(Attribute::Bold);
+            }
+            parsed.push((regex, style));
+        }
+
+        Ok(Highlighter {
+            rules: parsed,
+            line: String::new(),
+            escape: Escape::None,
+            csi: String::new(),
+            sgr: String::new(),
+        })
+    }
+
+    /// Add a rule that goes before the ones from the config
+    pub fn add_first(&mut self, regex: Regex, style: ContentStyle) {
+        self.rules.insert(0, (regex, style));
+    }
+
+    /* Follow escape sequences, returns whether `c` is part of one */
+    fn feed_escape(&mut self, c: char) -> bool {
+        let (escape, part) = match (self.escape, c) {
+            (Escape::None, '\x1b') => (Escape::Start, true),
+            (Escape::None, _) => (Escape::None, false),
+            (Escape::Start, '[') => {
+                self.csi.clear();
+                (Escape::Csi, true)
+            }
+            (Escape::Start, ']') => (Escape::Osc, true),
+            (Escape::Start, _) => (Escape::None, true),
+            /* Parameters and intermediates up to the final byte */
+            (Escape::Csi, '\x20'..='\x3f') => {
+                self.csi.push(c);
+                (Escape::Csi, true)
+            }
+            (Escape::Csi, 'm') => {
+                self.sgr_sequence();
+                (Escape::None, true)
+            }
+            (Escape::Csi, _) => (Escape::None, true),
+            (Escape::Osc, '\x07') => (Escape::None, true),
+            (Escape::Osc | Escape::OscEsc, '\x1b') => (Escape::OscEsc, true),
+            (Escape::OscEsc, '\\') => (Escape::None, true),
+            (Escape::Osc | Escape::OscEsc, _) => (Escape::Osc, true),
+        };
+        self.escape = escape;
+        part
+    }
+
+    fn sgr_sequence(&mut self) {
+        /* Everything before a reset doesn't matter anymore */
+        if self.csi.is_empty() || self.csi == "0" || self.csi.starts_with("0;") {
+            self.sgr.clear();
+        }
+        if !self.csi.is_empty() && self.csi != "0" {
+            write!(self.sgr, "\x1b[{}m", self.csi).unwrap();
+        }
+        if self.sgr.len() > MAX_SGR {
+            let cut = self.sgr.len() - MAX_SGR;
+            let start = self.sgr[cut..].find('\x1b').map_or(self.sgr.len(), |pos| cut + pos);
+            self.sgr.drain(..start);
+        }
+    }
+
+    /* Print a highlighted run and go back to the style of the device */
+    fn push_run(&self, out: &mut String, style: Option<usize>, run: &str) {
+        match style {
+            Some(idx) => {
+                write!(out, "{}", self.rules[idx].1.apply(run)).unwrap();
+                out.push_str(&self.sgr);
+            }
+            None => out.push_str(run),
+        }
+    }
+
+    /// The next piece of a line, with the matches highlighted. Escape
+    /// sequences of the device pass unchanged.
+    pub fn highlight(&mut self, text: &str) -> String {
+        if self.rules.is_empty() {
+            return text.to_string();
+        }
+
+        let offset = self.line.len();
+        self.line.push_str(text);
+
+        /* Style per byte of the new text */
+        let mut styles: Vec<Option<usize>> = vec![None; text.len()];
+        for (idx, (regex, _)) in self.rules.iter().enumerate() {
+            for found in regex.find_iter(&self.line) {
+                if found.end() <= offset {
+                    continue;
+                }
+                let start = found.start().max(offset) - offset;
+                let end = found.end() - offset;
+                for style in &mut styles[start..end] {
+                    style.get_or_insert(idx);
+                }
+            }
+        }
+
+        let mut out = String::with_capacity(text.len());
+        let mut run_style = None;
+        let mut run = String::new();
+        for (pos, c) in text.char_indices() {
+            let style = match self.feed_escape(c) {
+                true => None,
+                false => styles[pos],
+            };
+            if style != run_style && !run.is_empty() {
+                self.push_run(&mut out, run_style, &run);
+                run.clear();
+            }
+            run_style = style;
+            run.push(c);
+        }
+        self.push_run(&mut out, run_style, &run);
+
+        if text.ends_with('\n') || self.line.len() > MAX_LINE {
+            self.line.clear();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highlight::{HighlightRule, Highlighter};
+
+    fn rule(regex: &str) -> Highlighter {
+        let rule = HighlightRule {
+            regex: regex.to_string(),
+            fg: Some("red".to_string()),
+            bg: None,
+            bold: false,
+        };
+        Highlighter::from_config(Some(&[rule])).unwrap()
+    }
+
+    const RED: &str = "\x1b[38;5;9m";
+    const DEFAULT: &str = "\x1b[39m";
+
+    #[test]
+    fn runs() {
+        let mut highlighter = rule("error");
+        assert_eq!(highlighter.highlight("an error!\r\n"), format!("an {}error{}!\r\n", RED, DEFAULT));
+
+        /* A match split over two pieces of the line */
+        assert_eq!(highlighter.highlight("err"), "err");
+        assert_eq!(highlighter.highlight("or\n"), format!("{}or{}\n", RED, DEFAULT));
+
+        assert_eq!(Highlighter::from_config(Some(&[])).unwrap().highlight("error"), "error");
+    }
+
+    #[test]
+    fn device_colors() {
+        /* Blue continues after the highlight */
+        let mut highlighter = rule("error");
+        assert_eq!(
+            highlighter.highlight("\x1b[1m\x1b[34mblue error here\x1b[0m\n"),
+            format!("\x1b[1m\x1b[34mblue {}error{}\x1b[1m\x1b[34m here\x1b[0m\n", RED, DEFAULT)
+        );
+        assert_eq!(highlighter.highlight("error"), format!("{}error{}", RED, DEFAULT));
+
+        /* Escape sequences, also split ones, are never highlighted */
+        let mut highlighter = rule(r"\d+");
+        assert_eq!(highlighter.highlight("\x1b[3"), "\x1b[3");
+        assert_eq!(
+            highlighter.highlight("2m42\x1b]0;title 7\x07"),
+            format!("2m{}42{}\x1b[32m\x1b]0;title 7\x07", RED, DEFAULT)
+        );
+    }
+}
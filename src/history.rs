@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use std::{env, fs, path::PathBuf};
+
+/* Lines kept in the history file */
+const MAX_HISTORY: usize = 500;
+
+/// Lines sent in line edit mode, stored across sessions
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    /* Position while browsing with up/down, with the line that was being typed */
+    pos: Option<usize>,
+    draft: String,
+    /* Typed prefix and the last match while recalling with tab */
+    completion: Option<(String, usize)>,
+}
+
+fn default_path() -> Option<PathBuf> {
+    #[cfg(unix)]
+    let dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+    #[cfg(windows)]
+    let dir = env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    dir.map(|dir| dir.join("minircom").join("history"))
+}
+
+impl History {
+    /// Load the history file, a missing or unreadable file gives an empty history
+    pub fn load() -> History {
+        let path = default_path();
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|str| str.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        History {
+            entries,
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Add a sent line and write the history file
+    pub fn add(&mut self, line: &str) -> Result<()> {
+        self.reset();
+        if line.is_empty() || self.entries.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.drain(..self.entries.len() - MAX_HISTORY);
+        }
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+        }
+        let mut str = self.entries.join("\n");
+        str.push('\n');
+        fs::write(path, str).with_context(|| format!("Could not write {}", path.display()))
+    }
+
+    /// Stop browsing, e.g. when the line is sent or cleared
+    pub fn reset(&mut self) {
+        self.pos = None;
+        self.completion = None;
+    }
+
+    /// Older line, `current` is kept to come back to
+    pub fn prev(&mut self, current: &str) -> Option<&str> {
+        let pos = match self.pos {
+            Some(0) => return None,
+            Some(pos) => pos - 1,
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+        };
+        self.pos = Some(pos);
+        Some(&self.entries[pos])
+    }
+
+    /// Newer line, or the line that was being typed
+    pub fn next(&mut self) -> Option<&str> {
+        let pos = self.pos? + 1;
+        if pos < self.entries.len() {
+            self.pos = Some(pos);
+            Some(&self.entries[pos])
+        } else {
+            self.pos = None;
+            Some(&self.draft)
+        }
+    }
+
+    /// Most recent line starting with what was typed, older ones on repeated calls
+    pub fn complete(&mut self, current: &str) -> Option<&str> {
+        let (prefix, end) = match self.completion.take() {
+            Some(completion) => completion,
+            None => (current.to_string(), self.entries.len()),
+        };
+
+        /* Wrap around to the newest match when there is nothing older */
+        let matches = |entry: &String| entry.starts_with(&prefix) && entry != current;
+        let found = self.entries[..end]
+            .iter()
+            .rposition(matches)
+            .or_else(|| self.entries.iter().rposition(matches))?;
+
+        self.completion = Some((prefix, found));
+        Some(&self.entries[found])
+    }
+
+    pub fn end_completion(&mut self) {
+        self.completion = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::History;
+
+    /* Without a path, nothing is written */
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        for line in lines {
+            history.add(line).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn browse() {
+        let mut history = history(&["mmc dev 1", "", "printenv", "printenv", "boot"]);
+        assert_eq!(history.entries, ["mmc dev 1", "printenv", "boot"]);
+
+        assert_eq!(history.prev("setenv"), Some("boot"));
+        assert_eq!(history.prev("ignored"), Some("printenv"));
+        assert_eq!(history.prev(""), Some("mmc dev 1"));
+        assert_eq!(history.prev(""), None);
+        assert_eq!(history.next(), Some("printenv"));
+        assert_eq!(history.next(), Some("boot"));
+        /* Back to what was being typed */
+        assert_eq!(history.next(), Some("setenv"));
+        assert_eq!(history.next(), None);
+
+        assert_eq!(History::default().prev("x"), None);
+    }
+
+    #[test]
+    fn complete() {
+        let mut history = history(&["print a", "boot", "print b"]);
+        assert_eq!(history.complete("pr"), Some("print b"));
+        assert_eq!(history.complete("print b"), Some("print a"));
+        /* Wraps around */
+        assert_eq!(history.complete("print a"), Some("print b"));
+        history.end_completion();
+        assert_eq!(history.complete("x"), None);
+    }
+}
@@ -0,0 +1,125 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub enum EditResult {
+    None,
+    Submit(String),
+    Cancel,
+}
+
+/// Single line text editor used for prompts on the status line
+#[derive(Default)]
+pub struct LineEditor {
+    text: String,
+    /* Position in chars, not bytes */
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.chars().count();
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn byte_pos(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.text.len(), |(pos, _)| pos)
+    }
+
+    pub fn handle_key(&mut self, key_event: KeyEvent) -> EditResult {
+        let len = self.text.chars().count();
+        let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key_event.code {
+            KeyCode::Enter => {
+                let text = std::mem::take(&mut self.text);
+                self.cursor = 0;
+                return EditResult::Submit(text);
+            }
+            KeyCode::Esc => {
+                self.set_text("");
+                return EditResult::Cancel;
+            }
+            KeyCode::Char('c') if ctrl => {
+                self.set_text("");
+                return EditResult::Cancel;
+            }
+            KeyCode::Char('u') if ctrl => self.set_text(""),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = len,
+            KeyCode::Char(c) if !ctrl => {
+                let pos = self.byte_pos(self.cursor);
+                self.text.insert(pos, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let pos = self.byte_pos(self.cursor);
+                self.text.remove(pos);
+            }
+            KeyCode::Delete if self.cursor < len => {
+                let pos = self.byte_pos(self.cursor);
+                self.text.remove(pos);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            _ => (),
+        }
+        EditResult::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{EditResult, LineEditor};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    fn keys(editor: &mut LineEditor, codes: &[KeyCode]) {
+        for &code in codes {
+            editor.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn edit() {
+        let mut editor = LineEditor::default();
+        keys(&mut editor, &[KeyCode::Char('a'), KeyCode::Char('é'), KeyCode::Char('c')]);
+        keys(&mut editor, &[KeyCode::Left, KeyCode::Backspace, KeyCode::Char('b')]);
+        assert_eq!((editor.text(), editor.cursor()), ("abc", 2));
+        keys(&mut editor, &[KeyCode::Home, KeyCode::Delete, KeyCode::Left, KeyCode::Backspace]);
+        assert_eq!((editor.text(), editor.cursor()), ("bc", 0));
+        keys(&mut editor, &[KeyCode::End, KeyCode::Right, KeyCode::Char('d'), KeyCode::Delete]);
+        assert_eq!((editor.text(), editor.cursor()), ("bcd", 3));
+
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        editor.handle_key(ctrl('a'));
+        keys(&mut editor, &[KeyCode::Char('>')]);
+        assert_eq!((editor.text(), editor.cursor()), (">bcd", 1));
+        editor.handle_key(ctrl('e'));
+        assert_eq!(editor.cursor(), 4);
+
+        match editor.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)) {
+            EditResult::Submit(text) => assert_eq!(text, ">bcd"),
+            _ => panic!("expected submit"),
+        }
+        assert_eq!((editor.text(), editor.cursor()), ("", 0));
+
+        editor.set_text("x");
+        editor.handle_key(ctrl('u'));
+        assert_eq!(editor.text(), "");
+        editor.set_text("x");
+        assert!(matches!(editor.handle_key(ctrl('c')), EditResult::Cancel));
+        assert_eq!(editor.text(), "");
+    }
+}
@@ -0,0 +1,551 @@
+use crate::app::Commands;
+use crate::config::Config;
+use crate::newline::TxEnter;
+use anyhow::{bail, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::fmt;
+
+/// A key with modifiers as used in the config file, e.g. "ctrl-a", "q", "shift-F5"
+#[derive(Clone, Copy, PartialEq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+/// Terminals send CTRL-\ ] ^ _ as 0x1c..0x1f, which crossterm reports as
+/// CTRL-4..CTRL-7 (and CTRL-@ as CTRL-space). Map them back to what was typed.
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> (KeyCode, KeyModifiers) {
+    let code = match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+            let c = match c {
+                '4' => '\\',
+                '5' => ']',
+                '6' => '^',
+                '7' => '_',
+                ' ' => '@',
+                c => c.to_ascii_lowercase(),
+            };
+            KeyCode::Char(c)
+        }
+        code => code,
+    };
+
+    /* Shift is already part of the character */
+    let modifiers = match code {
+        KeyCode::Char(_) => modifiers - KeyModifiers::SHIFT,
+        _ => modifiers,
+    };
+    (code, modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT))
+}
+
+impl KeyBinding {
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> KeyBinding {
+        KeyBinding { code, modifiers }
+    }
+
+    pub fn parse(str: &str) -> Result<KeyBinding> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = str;
+
+        /* Strip the modifiers, what is left is the key itself (which may be '-') */
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("ctrl-") && rest.len() > 5 {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = &rest[5..];
+            } else if lower.starts_with("alt-") && rest.len() > 4 {
+                modifiers |= KeyModifiers::ALT;
+                rest = &rest[4..];
+            } else if lower.starts_with("shift-") && rest.len() > 6 {
+                modifiers |= KeyModifiers::SHIFT;
+                rest = &rest[6..];
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "enter" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "esc" => KeyCode::Esc,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                name => match name.strip_prefix('f').map(str::parse::<u8>) {
+                    Some(Ok(num)) if (1..=12).contains(&num) => KeyCode::F(num),
+                    _ => bail!("Unknown key '{}'", str),
+                },
+            },
+        };
+
+        let (code, modifiers) = normalize(code, modifiers);
+        Ok(KeyBinding { code, modifiers })
+    }
+
+    pub fn matches(&self, key_event: KeyEvent) -> bool {
+        *self == KeyBinding::from(key_event)
+    }
+
+    /// A plain printable character, not usable as escape key
+    pub fn is_plain_char(&self) -> bool {
+        matches!(self.code, KeyCode::Char(_))
+            && !self.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+    }
+
+    /// F1-F12 without CTRL, usable for macros
+    pub fn is_function_key(&self) -> bool {
+        matches!(self.code, KeyCode::F(_)) && !self.modifiers.contains(KeyModifiers::CONTROL)
+    }
+}
+
+impl From<KeyEvent> for KeyBinding {
+    fn from(key_event: KeyEvent) -> Self {
+        let (code, modifiers) = normalize(key_event.code, key_event.modifiers);
+        KeyBinding { code, modifiers }
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("CTRL-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("ALT-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            f.write_str("SHIFT-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(num) => write!(f, "F{}", num),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Escape key and the keys of the commands that may follow it
+pub struct Keymap {
+    escape: KeyBinding,
+    commands: Vec<(KeyBinding, Commands)>,
+}
+
+impl Keymap {
+    pub fn from_config(config: &Config) -> Result<Keymap> {
+        let escape = match &config.escape_key {
+            Some(key) => KeyBinding::parse(key).context("Invalid escape_key")?,
+            None => KeyBinding::new(KeyCode::Char('a'), KeyModifiers::CONTROL),
+        };
+        if escape.is_plain_char() {
+            bail!("escape_key '{}' needs ctrl or alt, it would be impossible to type", escape);
+        }
+
+        for name in config.keys.keys() {
+            if !Commands::ALL.iter().any(|cmd| cmd.name() == name) {
+                let names: Vec<&str> = Commands::ALL.iter().map(|cmd| cmd.name()).collect();
+                bail!("Unknown command '{}' in [keys] (known: {})", name, names.join(", "));
+            }
+        }
+
+        let mut commands: Vec<(KeyBinding, Commands)> = Vec::new();
+        for cmd in Commands::ALL {
+            let key = match config.keys.get(cmd.name()) {
+                Some(key) if key == "none" => continue,
+                Some(key) => KeyBinding::parse(key)
+                    .with_context(|| format!("Invalid key for '{}'", cmd.name()))?,
+                None => KeyBinding::new(KeyCode::Char(cmd.default_key()), KeyModifiers::NONE),
+            };
+
+            if key == escape {
+                bail!("'{}' can't use the escape key {}", cmd.name(), key);
+            }
+            if let Some((_, other)) = commands.iter().find(|(other_key, _)| *other_key == key) {
+                bail!("{} is bound to both '{}' and '{}'", key, other.name(), cmd.name());
+            }
+            commands.push((key, cmd));
+        }
+
+        Ok(Keymap { escape, commands })
+    }
+
+    pub fn escape_key(&self) -> KeyBinding {
+        self.escape
+    }
+
+    pub fn is_escape(&self, key_event: KeyEvent) -> bool {
+        self.escape.matches(key_event)
+    }
+
+    pub fn command(&self, key_event: KeyEvent) -> Option<Commands> {
+        let key = KeyBinding::from(key_event);
+        /* Allow CTRL to be still held down for plain keys, like minicom */
+        let plain = KeyBinding::new(key.code, key.modifiers - KeyModifiers::CONTROL);
+
+        self.commands
+            .iter()
+            .find(|(binding, _)| *binding == key)
+            .or_else(|| self.commands.iter().find(|(binding, _)| *binding == plain))
+            .map(|(_, cmd)| *cmd)
+    }
+
+    pub fn key_for(&self, cmd: Commands) -> Option<KeyBinding> {
+        self.commands
+            .iter()
+            .find(|(_, other)| *other == cmd)
+            .map(|(key, _)| *key)
+    }
+}
+
+/// What the Backspace and Delete keys send
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EraseCode {
+    /// 0x08
+    Bs,
+    /// 0x7f
+    Del,
+    /// ESC [ 3 ~
+    Vt220,
+}
+impl EraseCode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EraseCode::Bs => "BS",
+            EraseCode::Del => "DEL",
+            EraseCode::Vt220 => "VT220",
+        }
+    }
+}
+
+/// Turns key presses into the bytes an xterm would send
+pub struct KeyEncoder {
+    pub backspace: EraseCode,
+    pub delete: EraseCode,
+    pub enter: TxEnter,
+    /* DECCKM, cursor keys send ESC O x instead of ESC [ x */
+    app_cursor: bool,
+    parser: SeqParser,
+}
+
+impl Default for KeyEncoder {
+    fn default() -> Self {
+        KeyEncoder {
+            backspace: EraseCode::Bs,
+            delete: EraseCode::Vt220,
+            enter: TxEnter::Cr,
+            app_cursor: false,
+            parser: SeqParser::default(),
+        }
+    }
+}
+
+/* xterm modifier parameter, 1 + shift + 2 * alt + 4 * ctrl */
+fn modifier_param(modifiers: KeyModifiers) -> u8 {
+    let mut param = 1;
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        param += 1;
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        param += 2;
+    }
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        param += 4;
+    }
+    param
+}
+
+/* Control code for CTRL + key, like xterm */
+fn ctrl_code(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' => Some(c as u8 - b'a' + 1),
+        'A'..='Z' => Some(c as u8 - b'A' + 1),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '7' | '/' => Some(0x1f),
+        '?' | '8' => Some(0x7f),
+        _ => None,
+    }
+}
+
+impl KeyEncoder {
+    /// Watch the received data for the device switching the cursor key mode
+    pub fn scan(&mut self, data: &[u8]) {
+        for &byte in data {
+            match self.parser.feed(byte) {
+                Some(Seq::PrivateMode(params, set))
+                    if params.split(|&b| b == b';').any(|param| param == b"1") =>
+                {
+                    self.app_cursor = set;
+                }
+                Some(Seq::Reset) => self.app_cursor = false,
+                _ => (),
+            }
+        }
+    }
+
+    pub fn encode(&self, key_event: KeyEvent) -> Option<Vec<u8>> {
+        let modifiers = key_event.modifiers;
+        let alt = modifiers.contains(KeyModifiers::ALT);
+        let param = modifier_param(modifiers);
+
+        /* Cursor keys and F1-F4: SS3 x, or CSI 1 ; mod x when modified */
+        let ss3 = |final_byte: u8, app_mode: bool| -> Vec<u8> {
+            if param > 1 {
+                format!("\x1b[1;{}{}", param, final_byte as char).into_bytes()
+            } else if app_mode {
+                vec![0x1b, b'O', final_byte]
+            } else {
+                vec![0x1b, b'[', final_byte]
+            }
+        };
+        /* Editing keys and F5-F12: CSI num ~, or CSI num ; mod ~ when modified */
+        let tilde = |num: u8| -> Vec<u8> {
+            if param > 1 {
+                format!("\x1b[{};{}~", num, param).into_bytes()
+            } else {
+                format!("\x1b[{}~", num).into_bytes()
+            }
+        };
+        /* Alt sends ESC before the key */
+        let meta = |mut bytes: Vec<u8>| -> Vec<u8> {
+            if alt {
+                bytes.insert(0, 0x1b);
+            }
+            bytes
+        };
+
+        let erase = |code: EraseCode| -> Vec<u8> {
+            match code {
+                EraseCode::Bs => meta(vec![0x08]),
+                EraseCode::Del => meta(vec![0x7f]),
+                EraseCode::Vt220 => tilde(3),
+            }
+        };
+
+        let bytes = match key_event.code {
+            KeyCode::Backspace => erase(self.backspace),
+            KeyCode::Enter => meta(self.enter.bytes().to_vec()),
+            KeyCode::Tab => meta(vec![b'\t']),
+            KeyCode::BackTab => b"\x1b[Z".to_vec(),
+            KeyCode::Esc => meta(vec![0x1b]),
+            KeyCode::Null => vec![0x00],
+            KeyCode::Up => ss3(b'A', self.app_cursor),
+            KeyCode::Down => ss3(b'B', self.app_cursor),
+            KeyCode::Right => ss3(b'C', self.app_cursor),
+            KeyCode::Left => ss3(b'D', self.app_cursor),
+            KeyCode::Home => ss3(b'H', self.app_cursor),
+            KeyCode::End => ss3(b'F', self.app_cursor),
+            KeyCode::Insert => tilde(2),
+            KeyCode::Delete => erase(self.delete),
+            KeyCode::PageUp => tilde(5),
+            KeyCode::PageDown => tilde(6),
+            KeyCode::F(num @ 1..=4) => ss3(b'P' + num - 1, true),
+            KeyCode::F(num @ 5..=12) => {
+                let code = [15, 17, 18, 19, 20, 21, 23, 24];
+                tilde(code[num as usize - 5])
+            }
+            KeyCode::Char(c) => {
+                let ctrl = match modifiers.contains(KeyModifiers::CONTROL) {
+                    true => ctrl_code(c),
+                    false => None,
+                };
+                match ctrl {
+                    Some(code) => meta(vec![code]),
+                    None => {
+                        let mut buf: [u8; 4] = [0; 4];
+                        meta(c.encode_utf8(&mut buf).as_bytes().to_vec())
+                    }
+                }
+            }
+            _ => return None,
+        };
+        Some(bytes)
+    }
+}
+
+enum Seq {
+    /* CSI ? params h or l */
+    PrivateMode(Vec<u8>, bool),
+    /* ESC c */
+    Reset,
+}
+
+/// Minimal escape sequence parser, only keeps what is needed to follow
+/// the modes of the device. Sequences may be split over several reads.
+#[derive(Default)]
+struct SeqParser {
+    state: ParserState,
+    params: Vec<u8>,
+}
+
+#[derive(Default, PartialEq)]
+enum ParserState {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+}
+
+/* Longer parameter strings are not something we care about */
+const MAX_PARAMS: usize = 32;
+
+impl SeqParser {
+    fn feed(&mut self, byte: u8) -> Option<Seq> {
+        if byte == 0x1b {
+            self.state = ParserState::Escape;
+            return None;
+        }
+
+        match self.state {
+            ParserState::Ground => None,
+            ParserState::Escape => {
+                self.state = ParserState::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = ParserState::Csi;
+                        self.params.clear();
+                        None
+                    }
+                    b'c' => Some(Seq::Reset),
+                    _ => None,
+                }
+            }
+            ParserState::Csi => match byte {
+                0x30..=0x3f if self.params.len() < MAX_PARAMS => {
+                    self.params.push(byte);
+                    None
+                }
+                0x20..=0x3f => None,
+                0x40..=0x7e => {
+                    self.state = ParserState::Ground;
+                    match (self.params.first(), byte) {
+                        (Some(b'?'), b'h' | b'l') => {
+                            Some(Seq::PrivateMode(self.params[1..].to_vec(), byte == b'h'))
+                        }
+                        _ => None,
+                    }
+                }
+                /* C0 controls are executed in the middle of a sequence */
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::Commands;
+    use crate::config::Config;
+    use crate::keys::{EraseCode, KeyEncoder, Keymap};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    fn encode(encoder: &KeyEncoder, code: KeyCode, modifiers: KeyModifiers) -> Vec<u8> {
+        encoder.encode(KeyEvent::new(code, modifiers)).unwrap()
+    }
+
+    #[test]
+    fn matches() {
+        let keymap = Keymap::from_config(&Config::default()).unwrap();
+        for cmd in Commands::ALL {
+            let key = keymap.key_for(cmd).unwrap();
+            assert!(keymap.command(KeyEvent::new(key.code, key.modifiers)) == Some(cmd));
+        }
+    }
+
+    #[test]
+    fn function_keys() {
+        let encoder = KeyEncoder::default();
+        let expected: [&[u8]; 12] = [
+            b"\x1bOP", b"\x1bOQ", b"\x1bOR", b"\x1bOS", b"\x1b[15~", b"\x1b[17~",
+            b"\x1b[18~", b"\x1b[19~", b"\x1b[20~", b"\x1b[21~", b"\x1b[23~", b"\x1b[24~",
+        ];
+        for (num, expected) in (1..=12).zip(expected) {
+            assert_eq!(encode(&encoder, KeyCode::F(num), KeyModifiers::NONE), expected);
+        }
+        assert_eq!(encode(&encoder, KeyCode::F(1), KeyModifiers::SHIFT), b"\x1b[1;2P");
+        assert_eq!(encode(&encoder, KeyCode::F(5), KeyModifiers::CONTROL), b"\x1b[15;5~");
+    }
+
+    #[test]
+    fn ctrl_keys() {
+        let encoder = KeyEncoder::default();
+        let ctrl = KeyModifiers::CONTROL;
+        assert_eq!(encode(&encoder, KeyCode::Char('a'), ctrl), [0x01]);
+        assert_eq!(encode(&encoder, KeyCode::Char('z'), ctrl), [0x1a]);
+        assert_eq!(encode(&encoder, KeyCode::Char('C'), ctrl | KeyModifiers::SHIFT), [0x03]);
+        for (c, byte) in [('@', 0x00), ('[', 0x1b), ('\\', 0x1c), (']', 0x1d), ('^', 0x1e), ('_', 0x1f)] {
+            assert_eq!(encode(&encoder, KeyCode::Char(c), ctrl), [byte]);
+        }
+        /* What crossterm reports for the same keys */
+        for (c, byte) in [(' ', 0x00), ('4', 0x1c), ('5', 0x1d), ('6', 0x1e), ('7', 0x1f)] {
+            assert_eq!(encode(&encoder, KeyCode::Char(c), ctrl), [byte]);
+        }
+    }
+
+    #[test]
+    fn alt_and_utf8() {
+        let encoder = KeyEncoder::default();
+        let alt = KeyModifiers::ALT;
+        assert_eq!(encode(&encoder, KeyCode::Char('x'), alt), b"\x1bx");
+        assert_eq!(encode(&encoder, KeyCode::Char('a'), alt | KeyModifiers::CONTROL), b"\x1b\x01");
+        assert_eq!(encode(&encoder, KeyCode::Enter, alt), b"\x1b\r");
+        assert_eq!(encode(&encoder, KeyCode::Char('é'), KeyModifiers::NONE), "é".as_bytes());
+        assert_eq!(encode(&encoder, KeyCode::Char('€'), KeyModifiers::NONE), "€".as_bytes());
+        assert_eq!(encode(&encoder, KeyCode::Up, alt), b"\x1b[1;3A");
+    }
+
+    #[test]
+    fn erase_keys() {
+        let mut encoder = KeyEncoder::default();
+        assert_eq!(encode(&encoder, KeyCode::Backspace, KeyModifiers::NONE), [0x08]);
+        assert_eq!(encode(&encoder, KeyCode::Delete, KeyModifiers::NONE), b"\x1b[3~");
+
+        encoder.backspace = EraseCode::Del;
+        encoder.delete = EraseCode::Bs;
+        assert_eq!(encode(&encoder, KeyCode::Backspace, KeyModifiers::NONE), [0x7f]);
+        assert_eq!(encode(&encoder, KeyCode::Backspace, KeyModifiers::ALT), b"\x1b\x7f");
+        assert_eq!(encode(&encoder, KeyCode::Delete, KeyModifiers::NONE), [0x08]);
+    }
+
+    #[test]
+    fn application_cursor_mode() {
+        let mut encoder = KeyEncoder::default();
+        assert_eq!(encode(&encoder, KeyCode::Up, KeyModifiers::NONE), b"\x1b[A");
+
+        /* Split over reads, and combined with another mode */
+        encoder.scan(b"hello\x1b[?25;");
+        encoder.scan(b"1h");
+        assert!(encoder.app_cursor);
+        assert_eq!(encode(&encoder, KeyCode::Up, KeyModifiers::NONE), b"\x1bOA");
+        assert_eq!(encode(&encoder, KeyCode::Home, KeyModifiers::NONE), b"\x1bOH");
+        assert_eq!(encode(&encoder, KeyCode::Left, KeyModifiers::CONTROL), b"\x1b[1;5D");
+
+        encoder.scan(b"\x1b[?12l");
+        assert!(encoder.app_cursor);
+        encoder.scan(b"\x1b[?1l");
+        assert!(!encoder.app_cursor);
+
+        encoder.scan(b"\x1b[?1h\x1bc");
+        assert!(!encoder.app_cursor);
+    }
+}
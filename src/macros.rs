@@ -0,0 +1,174 @@
+use crate::config::Config;
+use crate::escape::unescape;
+use crate::keys::KeyBinding;
+use anyhow::{anyhow, bail, Context, Result};
+use crossterm::event::KeyEvent;
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+/* Macros are set in the [macros] section of the config file:
+ *
+ *   [macros]
+ *   F5 = 'setenv bootargs console=ttymxc0,115200\r'
+ *   shift-F5 = 'mmc dev 1\r{sleep 500}mmc info\r'
+ *   alt-F12 = '\x03'
+ *
+ * Use single quoted (literal) TOML strings, TOML itself doesn't know \x03. */
+
+#[derive(Clone)]
+pub enum Step {
+    Send(Vec<u8>),
+    Sleep(Duration),
+}
+
+pub struct Macro {
+    key: KeyBinding,
+    /* As written in the config, for the help screen */
+    text: String,
+    steps: Vec<Step>,
+}
+
+impl Macro {
+    pub fn key(&self) -> KeyBinding {
+        self.key
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Split a macro string in the data to send and the `{sleep MS}` delays in between
+fn parse_steps(text: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{sleep ") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("missing '}}' after {{sleep"))?;
+        let ms = rest[start + 7..start + end].trim();
+        let ms: u64 = ms
+            .parse()
+            .map_err(|_| anyhow!("invalid sleep '{}' (expected milliseconds)", ms))?;
+
+        if start > 0 {
+            steps.push(Step::Send(unescape(&rest[..start])?));
+        }
+        steps.push(Step::Sleep(Duration::from_millis(ms)));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        steps.push(Step::Send(unescape(rest)?));
+    }
+    Ok(steps)
+}
+
+pub fn from_config(config: &Config, escape: KeyBinding) -> Result<Vec<Macro>> {
+    let mut macros: Vec<Macro> = Vec::new();
+
+    for (key, text) in &config.macros {
+        let binding = KeyBinding::parse(key).with_context(|| format!("Invalid macro key '{}'", key))?;
+        if !binding.is_function_key() {
+            bail!("Macro key '{}' must be F1-F12, optionally with shift or alt", key);
+        }
+        if binding == escape {
+            bail!("Macro key '{}' is the escape key", key);
+        }
+        if macros.iter().any(|other| other.key == binding) {
+            bail!("Macro key {} is defined twice", binding);
+        }
+
+        let steps = parse_steps(text).with_context(|| format!("Invalid macro for {}", binding))?;
+        macros.push(Macro {
+            key: binding,
+            text: text.clone(),
+            steps,
+        });
+    }
+    Ok(macros)
+}
+
+/// Steps of the macros that were triggered but not sent yet
+#[derive(Default)]
+pub struct MacroPlayer {
+    queue: VecDeque<Step>,
+    wait_until: Option<Instant>,
+}
+
+impl MacroPlayer {
+    pub fn start(&mut self, mac: &Macro) {
+        self.queue.extend(mac.steps.iter().cloned());
+    }
+
+    /// Data that may be sent now, call again later when a sleep is pending
+    pub fn poll(&mut self, now: Instant) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        if let Some(deadline) = self.wait_until {
+            if now < deadline {
+                return data;
+            }
+            self.wait_until = None;
+        }
+
+        while let Some(step) = self.queue.pop_front() {
+            match step {
+                Step::Send(bytes) => data.extend_from_slice(&bytes),
+                Step::Sleep(duration) => {
+                    self.wait_until = Some(now + duration);
+                    break;
+                }
+            }
+        }
+        data
+    }
+}
+
+pub fn find(macros: &[Macro], key_event: KeyEvent) -> Option<&Macro> {
+    macros.iter().find(|mac| mac.key.matches(key_event))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::macros::{parse_steps, MacroPlayer, Step};
+    use tokio::time::{Duration, Instant};
+
+    fn steps(text: &str) -> Vec<String> {
+        parse_steps(text)
+            .unwrap()
+            .into_iter()
+            .map(|step| match step {
+                Step::Send(data) => format!("{:?}", String::from_utf8_lossy(&data)),
+                Step::Sleep(duration) => format!("sleep {}", duration.as_millis()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sleeps() {
+        assert_eq!(steps(r"mmc dev 1\r"), [r#""mmc dev 1\r""#]);
+        assert_eq!(
+            steps(r"a\r{sleep 500}b{sleep  20 }{sleep 0}"),
+            [r#""a\r""#, "sleep 500", r#""b""#, "sleep 20", "sleep 0"]
+        );
+        assert_eq!(steps("{sleep 1}x"), ["sleep 1", r#""x""#]);
+        assert_eq!(steps(""), Vec::<String>::new());
+
+        let error = |text: &str| format!("{:#}", parse_steps(text).err().unwrap());
+        assert_eq!(error("a{sleep 500"), "missing '}' after {sleep");
+        assert_eq!(error("{sleep 0.5}"), "invalid sleep '0.5' (expected milliseconds)");
+        assert_eq!(error("{sleep }"), "invalid sleep '' (expected milliseconds)");
+        assert!(parse_steps(r"\xzz{sleep 1}").is_err());
+    }
+
+    #[test]
+    fn player() {
+        let mut player = MacroPlayer::default();
+        player.queue.extend(parse_steps("a{sleep 100}b").unwrap());
+        let t0 = Instant::now();
+        assert_eq!(player.poll(t0), b"a");
+        assert_eq!(player.poll(t0 + Duration::from_millis(99)), b"");
+        assert_eq!(player.poll(t0 + Duration::from_millis(100)), b"b");
+    }
+}
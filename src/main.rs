@@ -1,15 +1,16 @@
 use anyhow::{Error, Result};
 use clap::builder::TypedValueParser;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossterm::event::{Event, EventStream};
 use futures::StreamExt;
-use std::path::PathBuf;
+use std::{path::PathBuf, process::ExitCode};
+use time::UtcOffset;
 use tokio::{
     io::AsyncReadExt,
     select,
     time::{interval, Duration},
 };
-use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
 
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
@@ -17,8 +18,38 @@ use tokio::signal::unix::{signal, SignalKind};
 use tokio::signal::windows::ctrl_close;
 
 mod app;
+mod batch;
+mod checksum;
+mod config;
+mod decoder;
+mod defmt;
+mod escape;
+mod filter;
+mod framing;
+mod highlight;
+mod history;
+mod input;
+mod keys;
+mod macros;
+mod modbus;
+mod newline;
+mod nmea;
+mod plot;
+mod runner;
+mod rx_filter;
+mod script;
+mod server;
+mod stats;
+mod trigger;
 mod tui;
-use app::{App, AppResults, TICKS_MS};
+#[cfg(unix)]
+mod virtual_port;
+use app::{bits_per_char, App, AppResults, TICKS_MS};
+use checksum::Checksum;
+use config::Config;
+use framing::Framing;
+use server::Server;
+use stats::Stats;
 
 #[cfg(unix)]
 const DEFAULT_TTY: &str = "/dev/ttyS0";
@@ -28,16 +59,20 @@ const DEFAULT_TTY: &str = "COM1";
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Config file (default: ~/.config/minircom/config.toml when it exists)
     #[arg(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
 
-    #[arg(short = 'D', long, default_value = DEFAULT_TTY)]
+    #[arg(short = 'D', long, default_value = DEFAULT_TTY, global = true)]
     device: String,
 
-    #[arg(short, long, default_value_t = 115200)]
+    #[arg(short, long, default_value_t = 115200, global = true)]
     baud_rate: u32,
 
-    #[arg(short = 'B', long, default_value = "8",
+    #[arg(short = 'B', long, default_value = "8", global = true,
         value_parser = clap::builder::PossibleValuesParser::new(["5", "6", "7", "8"])
             .map(|s| match s.as_str() {
             "5" => tokio_serial::DataBits::Five,
@@ -48,7 +83,7 @@ pub struct Cli {
         }))]
     data_bits: tokio_serial::DataBits,
 
-    #[arg(short, long, default_value = "none",
+    #[arg(short, long, default_value = "none", global = true,
         value_parser = clap::builder::PossibleValuesParser::new(["none", "odd", "even"])
             .map(|s| match s.as_str() {
             "none" => tokio_serial::Parity::None,
@@ -58,7 +93,7 @@ pub struct Cli {
         }))]
     parity: tokio_serial::Parity,
 
-    #[arg(short, long, default_value = "1",
+    #[arg(short, long, default_value = "1", global = true,
         value_parser = clap::builder::PossibleValuesParser::new(["1", "2"])
             .map(|s| match s.as_str() {
             "1" => tokio_serial::StopBits::One,
@@ -67,7 +102,7 @@ pub struct Cli {
         }))]
     stop_bits: tokio_serial::StopBits,
 
-    #[arg(short, long, default_value = "none",
+    #[arg(short, long, default_value = "none", global = true,
         value_parser = clap::builder::PossibleValuesParser::new(["none", "software", "hardware"])
             .map(|s| match s.as_str() {
             "none" => tokio_serial::FlowControl::None,
@@ -76,12 +111,103 @@ pub struct Cli {
             _ => unreachable!(),
         }))]
     flow_control: tokio_serial::FlowControl,
+
+    /// Share the port with TCP clients (e.g. 127.0.0.1:7000)
+    #[arg(short, long, value_name = "ADDR")]
+    listen: Option<String>,
+
+    /// Don't forward input from TCP clients to the port
+    #[arg(long, requires = "listen")]
+    listen_read_only: bool,
+
+    /// Create a pty pair and connect to one end instead of opening DEVICE
+    #[cfg(unix)]
+    #[arg(long = "virtual", global = true)]
+    virtual_port: bool,
+
+    /// Simulate a device on the other end of the virtual port
+    #[cfg(unix)]
+    #[arg(long, value_name = "MODE", requires = "virtual_port", global = true)]
+    simulate: Option<virtual_port::Simulator>,
+
+    /// Run a script (see script.rs for the commands) once connected
+    #[arg(long, value_name = "FILE", conflicts_with = "batch")]
+    script: Option<PathBuf>,
+
+    /// Headless mode for pipelines: stdin to the port, the port to stdout
+    #[arg(long, conflicts_with = "listen")]
+    batch: bool,
+
+    /// Keep reading the port this long after stdin is closed (batch mode)
+    #[arg(long, value_name = "MS", default_value_t = 0, requires = "batch")]
+    drain_ms: u64,
+
+    /// Show received data as hex
+    #[arg(long)]
+    hex: bool,
+
+    /// Decode received data as rzcobs framed defmt logs, with the table from ELF
+    #[arg(long, value_name = "ELF", conflicts_with_all = ["framing", "modbus"])]
+    defmt: Option<PathBuf>,
+
+    /// Split received data in frames and show every frame as a line of hex
+    #[arg(long, value_name = "KIND", conflicts_with = "modbus")]
+    framing: Option<Framing>,
+
+    /// Checksum at the end of every frame: crc16 (Modbus), crc8, sum8 or fcs16 (HDLC)
+    #[arg(long, value_name = "CHECK", value_parser = Checksum::parse, requires = "framing")]
+    frame_check: Option<Checksum>,
+
+    /// Split received data in Modbus RTU frames on silence and decode them
+    #[arg(long)]
+    modbus: bool,
+
+    /// Check NMEA 0183 sentences and show the GNSS status in a panel
+    #[arg(long, conflicts_with_all = ["defmt", "framing", "modbus"])]
+    nmea: bool,
+
+    /// Plot the numbers in the capture groups of REGEX in every line
+    #[arg(long, value_name = "REGEX", conflicts_with = "plot_columns")]
+    plot_regex: Option<String>,
+
+    /// Plot these CSV columns of every line (counting from 1), e.g. 2,3
+    #[arg(long, value_name = "N,..", value_delimiter = ',')]
+    plot_columns: Option<Vec<usize>>,
+
+    /// Pipe received data through CMD (run with the shell) and show its output,
+    /// the decoded lines with --defmt, --framing, --modbus or --nmea.
+    /// CMD should not buffer its output, e.g. use `sed -u` or `stdbuf -oL`
+    #[arg(long, value_name = "CMD")]
+    rx_filter: Option<String>,
+
+    /// Write everything received to FILE (appends)
+    #[arg(long, value_name = "FILE")]
+    capture: Option<PathBuf>,
+
+    /// After this much silence end the line with an idle marker (a new row in hex mode)
+    #[arg(long, value_name = "MS")]
+    idle_gap: Option<u64>,
+
+    /// Path of the other end of the virtual port (set at runtime)
+    #[arg(skip)]
+    virtual_peer: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Non-interactive: send, then wait for a pattern (exit 0 match, 1 failure, 2 timeout, 3 error)
+    Run(runner::RunArgs),
 }
 
-async fn event_handler(app: &mut App, port: &mut SerialStream) -> Result<()> {
+async fn event_handler(
+    app: &mut App,
+    port: &mut SerialStream,
+    mut server: Option<Server>,
+) -> Result<()> {
     let mut buf: [u8; 128] = [0; 128];
     let mut reader = EventStream::new();
     let mut interval = interval(Duration::from_millis(TICKS_MS));
+    let mut filter_output = app.take_filter_output();
 
     /* No idea if this works on windows... */
     #[cfg(unix)]
@@ -93,7 +219,7 @@ async fn event_handler(app: &mut App, port: &mut SerialStream) -> Result<()> {
         select! {
             /* Tick */
             _ = interval.tick() => {
-                match app.tick() {
+                match app.tick(port) {
                     Ok(_) => (),
                     Err(e) => {
                         println!("Error: {:?}", e);
@@ -107,7 +233,10 @@ async fn event_handler(app: &mut App, port: &mut SerialStream) -> Result<()> {
                 match maybe_event {
                     Ok(read_bytes) => {
                         let slice = &buf[0..read_bytes];
-                        app.handle_serial_event(slice)?;
+                        if let Some(server) = &server {
+                            server.broadcast(slice);
+                        }
+                        app.handle_serial_event(port, slice)?;
                     },
                     Err(e) => {
                         println!("Error: {:?}", e);
@@ -146,6 +275,21 @@ async fn event_handler(app: &mut App, port: &mut SerialStream) -> Result<()> {
                 }
             }
 
+            /* Output of the RX filter command */
+            Some(output) = filter_output.recv() => {
+                app.handle_filter_output(output)?;
+            }
+
+            /* Network clients (disabled when not listening) */
+            Some(data) = async {
+                match server.as_mut() {
+                    Some(server) => server.recv().await,
+                    None => None,
+                }
+            } => {
+                app.handle_remote_data(port, &data)?;
+            }
+
             /* Exit when needed */
             _ = sig_term.recv() => {
                 // TODO parse the result?
@@ -156,14 +300,7 @@ async fn event_handler(app: &mut App, port: &mut SerialStream) -> Result<()> {
     Ok(())
 }
 
-async fn main_app() -> Result<()> {
-    let cli = Cli::parse();
-
-    // TODO actually use config file
-    if let Some(config_path) = cli.config.as_deref() {
-        println!("Value for config: {}", config_path.display());
-    }
-
+fn open_port(cli: &Cli) -> Result<SerialStream> {
     /* Set default options */
     let builder = tokio_serial::new(cli.device.clone(), cli.baud_rate)
         .data_bits(cli.data_bits)
@@ -179,11 +316,73 @@ async fn main_app() -> Result<()> {
     port.set_exclusive(false)
         .expect("Unable to set serial port exclusive to false");
 
-    let mut app = App::init(cli)?;
-    let result = event_handler(&mut app, &mut port).await;
+    /* The DTR triggers start from on, whatever the driver did on open
+     * (ports without modem lines just fail) */
+    let _ = port.write_data_terminal_ready(true);
+
+    Ok(port)
+}
+
+#[cfg(unix)]
+fn connect(cli: &mut Cli) -> Result<(SerialStream, Option<virtual_port::Peer>)> {
+    if cli.virtual_port {
+        let (port, peer) = virtual_port::open(cli.simulate)?;
+        cli.virtual_peer = Some(peer.name.clone());
+        Ok((port, Some(peer)))
+    } else {
+        Ok((open_port(cli)?, None))
+    }
+}
+
+#[cfg(windows)]
+fn connect(cli: &mut Cli) -> Result<(SerialStream, Option<()>)> {
+    Ok((open_port(cli)?, None))
+}
+
+async fn main_app(local_offset: UtcOffset) -> Result<ExitCode> {
+    let mut cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        /* clap exits with 2, which means a timeout for run */
+        Err(e) if e.use_stderr() && std::env::args_os().any(|arg| arg == "run") => {
+            let _ = e.print();
+            return Ok(ExitCode::from(runner::EXIT_ERROR));
+        }
+        Err(e) => e.exit(),
+    };
+
+    let connected = connect(&mut cli);
+
+    if let Some(Command::Run(args)) = &cli.command {
+        return match connected {
+            Ok((mut port, _peer)) => Ok(runner::run(&mut port, args).await),
+            Err(e) => {
+                eprintln!("minircom: {:#}", e);
+                Ok(ExitCode::from(runner::EXIT_ERROR))
+            }
+        };
+    }
+    let (mut port, _peer) = connected?;
+
+    if cli.batch {
+        return batch::run(&mut port, Duration::from_millis(cli.drain_ms)).await;
+    }
+
+    /* Before anything slow, the line errors count from the open */
+    let stats = Stats::new(&port, cli.baud_rate, bits_per_char(&cli));
+
+    let config = Config::load(cli.config.as_deref())?;
+
+    let server = match cli.listen.as_deref() {
+        Some(addr) => Some(Server::bind(addr, cli.listen_read_only).await?),
+        None => None,
+    };
+
+    let mut app = App::init(cli, config, local_offset, stats)?;
+    let result = event_handler(&mut app, &mut port, server).await;
     app.cleanup()?;
+    eprintln!("{}", app.stats_summary(&port));
 
-    result
+    result.map(|_| ExitCode::SUCCESS)
 }
 
 // TODO?
@@ -197,8 +396,12 @@ async fn main_app() -> Result<()> {
 //     }));
 // }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    main_app().await?;
-    Ok(())
+fn main() -> Result<ExitCode> {
+    /* Only works while there is a single thread, so before the runtime starts */
+    let local_offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(main_app(local_offset))
 }
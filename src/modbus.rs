@@ -0,0 +1,325 @@
+use crate::checksum::Checksum;
+use anyhow::{bail, Context, Result};
+use std::fmt::Write;
+use tokio::time::{Duration, Instant};
+
+/* Requests are entered as the slave address, the function code and its
+ * arguments, decimal or with 0x for hex:
+ *
+ *   1 3 0 10          read 10 holding registers from 0
+ *   1 4 100 2         read 2 input registers from 100
+ *   1 1 0 16          read 16 coils, 2 the same for discrete inputs
+ *   1 5 172 1         switch coil 172 on
+ *   1 6 1 300         write 300 to register 1
+ *   1 15 19 1 0 1     write coils 19, 20 and 21
+ *   1 16 1 10 258     write registers 1 and 2
+ */
+
+/* No frame is longer than this */
+const MAX_FRAME: usize = 256;
+
+/// Splits received data in Modbus RTU frames on the 3.5 character silence and
+/// decodes them
+pub struct ModbusDecoder {
+    t35: Duration,
+    frame: Vec<u8>,
+    last_rx: Option<Instant>,
+    /* Slave, function and quantity of the last request, to recognize its response */
+    request: Option<(u8, u8, u16)>,
+}
+
+fn function_name(function: u8) -> &'static str {
+    match function {
+        1 => "read coils",
+        2 => "read discrete inputs",
+        3 => "read holding registers",
+        4 => "read input registers",
+        5 => "write coil",
+        6 => "write register",
+        15 => "write coils",
+        16 => "write registers",
+        _ => "function",
+    }
+}
+
+fn exception_name(code: u8) -> &'static str {
+    match code {
+        1 => "illegal function",
+        2 => "illegal data address",
+        3 => "illegal data value",
+        4 => "server device failure",
+        5 => "acknowledge",
+        6 => "server device busy",
+        8 => "memory parity error",
+        10 => "gateway path unavailable",
+        11 => "gateway target failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+fn word(data: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([data[pos], data[pos + 1]])
+}
+
+fn bits(data: &[u8], count: usize) -> String {
+    (0..count.min(data.len() * 8))
+        .map(|bit| if data[bit / 8] & (1 << (bit % 8)) != 0 { "1" } else { "0" })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn words(data: &[u8]) -> String {
+    data.chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]).to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/* 3.5 characters of 11 bits, fixed at 1.75 ms above 19200 baud */
+fn frame_gap(baud_rate: u32) -> Duration {
+    match baud_rate {
+        0..=19200 => Duration::from_micros(3_500_000 * 11 / baud_rate.max(1) as u64),
+        _ => Duration::from_micros(1750),
+    }
+}
+
+impl ModbusDecoder {
+    pub fn new(baud_rate: u32) -> ModbusDecoder {
+        ModbusDecoder {
+            t35: frame_gap(baud_rate),
+            frame: Vec::new(),
+            last_rx: None,
+            request: None,
+        }
+    }
+
+    /// The frame gap depends on the baud rate, call this when it changes
+    pub fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.t35 = frame_gap(baud_rate);
+    }
+
+    /// Data received at `now`, returns the line of the frame that ended before it
+    pub fn feed(&mut self, data: &[u8], now: Instant) -> String {
+        let mut lines = self.poll(now);
+        for &byte in data {
+            if self.frame.len() == MAX_FRAME {
+                lines += &self.end_frame();
+            }
+            self.frame.push(byte);
+        }
+        self.last_rx = Some(now);
+        lines
+    }
+
+    /// Returns the line of the frame when the silence after it is long enough
+    pub fn poll(&mut self, now: Instant) -> String {
+        match self.last_rx {
+            Some(last_rx) if now - last_rx >= self.t35 && !self.frame.is_empty() => self.end_frame(),
+            _ => String::new(),
+        }
+    }
+
+    /// A request we sent, so its response is recognized
+    pub fn sent(&mut self, frame: &[u8]) {
+        if frame.len() >= 4 {
+            self.decode(&frame[..frame.len() - 2]);
+        }
+    }
+
+    fn end_frame(&mut self) -> String {
+        let frame = std::mem::take(&mut self.frame);
+        let mut line = String::new();
+        for byte in &frame {
+            write!(line, "{:02x} ", byte).unwrap();
+        }
+        line.push(' ');
+
+        if frame.len() < 4 {
+            line += "too short";
+        } else {
+            let (pdu, crc) = frame.split_at(frame.len() - 2);
+            let expected = Checksum::Crc16.compute(pdu);
+            if expected == crc {
+                line += &self.decode(pdu);
+            } else {
+                write!(line, "CRC fail, expected {:02x} {:02x}", expected[0], expected[1]).unwrap();
+            }
+        }
+        line += "\r\n";
+        line
+    }
+
+    /* Frame without the CRC */
+    fn decode(&mut self, pdu: &[u8]) -> String {
+        let (slave, function, data) = (pdu[0], pdu[1], &pdu[2..]);
+        let name = function_name(function & 0x7f);
+        let request = self.request.take();
+        let mut text = format!("slave {}: ", slave);
+
+        if function & 0x80 != 0 {
+            let code = data.first().copied().unwrap_or(0);
+            write!(text, "{} exception {}, {}", name, code, exception_name(code)).unwrap();
+            return text;
+        }
+
+        let is_response = |data: &[u8]| {
+            let fits = data.first().map(|&count| count as usize == data.len() - 1) == Some(true);
+            match request {
+                Some((req_slave, req_function, _)) if (req_slave, req_function) == (slave, function) => fits,
+                _ => fits && data.len() != 4,
+            }
+        };
+
+        match function {
+            1..=4 if is_response(data) => {
+                let values = &data[1..];
+                let values = match function {
+                    1 | 2 => bits(values, request.map(|(_, _, count)| count as usize).unwrap_or(values.len() * 8)),
+                    _ => words(values),
+                };
+                write!(text, "{}: {}", name.trim_start_matches("read "), values).unwrap();
+            }
+            1..=4 if data.len() == 4 => {
+                let count = word(data, 2);
+                write!(text, "{} addr {} count {}", name, word(data, 0), count).unwrap();
+                self.request = Some((slave, function, count));
+            }
+            5 if data.len() == 4 => {
+                let state = match word(data, 2) {
+                    0xff00 => "on",
+                    0x0000 => "off",
+                    _ => "invalid",
+                };
+                write!(text, "{} addr {} {}", name, word(data, 0), state).unwrap();
+            }
+            6 if data.len() == 4 => {
+                write!(text, "{} addr {} value {}", name, word(data, 0), word(data, 2)).unwrap();
+            }
+            15 | 16 if data.len() == 4 => {
+                write!(text, "{} addr {} count {} done", name, word(data, 0), word(data, 2)).unwrap();
+            }
+            15 | 16 if data.len() > 5 && data[4] as usize == data.len() - 5 => {
+                let count = word(data, 2);
+                let values = match function {
+                    15 => bits(&data[5..], count as usize),
+                    _ => words(&data[5..]),
+                };
+                write!(text, "{} addr {} count {}: {}", name, word(data, 0), count, values).unwrap();
+                self.request = Some((slave, function, count));
+            }
+            _ => {
+                write!(text, "function {} with {} bytes", function, data.len()).unwrap();
+            }
+        }
+        text
+    }
+}
+
+fn parse_number(str: &str) -> Result<u16> {
+    let value = match str.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => str.parse(),
+    };
+    value.with_context(|| format!("Invalid number '{}'", str))
+}
+
+/// Build a request frame, CRC included, from e.g. "1 3 0 10"
+pub fn build_request(text: &str) -> Result<Vec<u8>> {
+    let numbers = text.split_whitespace().map(parse_number).collect::<Result<Vec<_>>>()?;
+    let (slave, function, args) = match numbers[..] {
+        [slave, function, ref args @ ..] if slave <= 247 && function <= 127 => (slave as u8, function as u8, args),
+        [_, _, ..] => bail!("Slave address or function code out of range"),
+        _ => bail!("Enter the slave address, function code and arguments"),
+    };
+
+    let mut frame = vec![slave, function];
+    match (function, args) {
+        (1..=4 | 6, [addr, value]) => {
+            frame.extend(addr.to_be_bytes());
+            frame.extend(value.to_be_bytes());
+        }
+        (5, [addr, value]) => {
+            frame.extend(addr.to_be_bytes());
+            frame.extend(if *value != 0 { [0xff, 0x00] } else { [0x00, 0x00] });
+        }
+        (15, [addr, values @ ..]) if !values.is_empty() && values.len() <= 1968 => {
+            let mut bytes = vec![0u8; values.len().div_ceil(8)];
+            for (bit, value) in values.iter().enumerate() {
+                if *value != 0 {
+                    bytes[bit / 8] |= 1 << (bit % 8);
+                }
+            }
+            frame.extend(addr.to_be_bytes());
+            frame.extend((values.len() as u16).to_be_bytes());
+            frame.push(bytes.len() as u8);
+            frame.extend(bytes);
+        }
+        (16, [addr, values @ ..]) if !values.is_empty() && values.len() <= 123 => {
+            frame.extend(addr.to_be_bytes());
+            frame.extend((values.len() as u16).to_be_bytes());
+            frame.push(values.len() as u8 * 2);
+            for value in values {
+                frame.extend(value.to_be_bytes());
+            }
+        }
+        (1..=6 | 15 | 16, _) => bail!("Wrong arguments for {}", function_name(function)),
+        /* Anything else is sent as raw bytes */
+        (_, args) => {
+            for arg in args {
+                frame.push(u8::try_from(*arg).with_context(|| format!("{} is not a byte", arg))?);
+            }
+        }
+    }
+    frame.extend(Checksum::Crc16.compute(&frame));
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::modbus::{build_request, ModbusDecoder};
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn requests_and_responses() {
+        assert_eq!(build_request("1 3 0 10").unwrap(), [0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd]);
+        assert_eq!(
+            build_request("0x11 15 0x13 1 0 1 1 0 0 1 1 1 0").unwrap()[..9],
+            [0x11, 0x0f, 0x00, 0x13, 0x00, 0x0a, 0x02, 0xcd, 0x01]
+        );
+        assert!(build_request("1 6 1").is_err());
+        let coils = |count| format!("1 15 0{}", " 1".repeat(count));
+        assert_eq!(build_request(&coils(1968)).unwrap()[6], 246);
+        assert!(build_request(&coils(1969)).is_err());
+
+        let mut decoder = ModbusDecoder::new(9600);
+        let t0 = Instant::now();
+        let gap = Duration::from_millis(10);
+
+        /* The request arrives in two reads */
+        assert_eq!(decoder.feed(&[0x01, 0x03, 0x00, 0x00], t0), "");
+        assert_eq!(decoder.feed(&[0x00, 0x02, 0xc4, 0x0b], t0 + Duration::from_millis(1)), "");
+        assert_eq!(
+            decoder.feed(&[0x01, 0x03, 0x04, 0x00, 0x0a, 0x01, 0x02], t0 + gap),
+            "01 03 00 00 00 02 c4 0b  slave 1: read holding registers addr 0 count 2\r\n"
+        );
+        assert_eq!(decoder.feed(&[0x5a, 0x60], t0 + gap), "");
+        assert_eq!(decoder.poll(t0 + gap * 2), "01 03 04 00 0a 01 02 5a 60  slave 1: holding registers: 10 258\r\n");
+
+        decoder.feed(&[0x01, 0x83, 0x02, 0xc0, 0xf1], t0 + gap * 3);
+        assert_eq!(decoder.poll(t0 + gap * 4), "01 83 02 c0 f1  slave 1: read holding registers exception 2, illegal data address\r\n");
+
+        decoder.feed(&[0x01, 0x83, 0x02, 0xc0, 0xf2], t0 + gap * 5);
+        assert_eq!(decoder.poll(t0 + gap * 6), "01 83 02 c0 f2  CRC fail, expected c0 f1\r\n");
+    }
+
+    #[test]
+    fn baud_rate_change() {
+        /* 4 ms is a gap at 115200 baud but not at 9600 */
+        let mut decoder = ModbusDecoder::new(9600);
+        let t0 = Instant::now();
+        decoder.feed(&[0x01, 0x83, 0x02, 0xc0, 0xf1], t0);
+        assert_eq!(decoder.poll(t0 + Duration::from_millis(4)), "");
+        decoder.set_baud_rate(115200);
+        assert_eq!(decoder.poll(t0 + Duration::from_millis(4)), "01 83 02 c0 f1  slave 1: read holding registers exception 2, illegal data address\r\n");
+    }
+}
@@ -0,0 +1,120 @@
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// Newline translation of received data, like picocom's --imap
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RxMap {
+    /// Show as received
+    None,
+    /// CR -> CR LF
+    Crcrlf,
+    /// LF -> CR LF
+    Lfcrlf,
+    /// CR -> LF
+    Crlf,
+    /// Drop CR
+    Igncr,
+}
+
+impl RxMap {
+    pub fn next(&self) -> Self {
+        match *self {
+            RxMap::None => RxMap::Crcrlf,
+            RxMap::Crcrlf => RxMap::Lfcrlf,
+            RxMap::Lfcrlf => RxMap::Crlf,
+            RxMap::Crlf => RxMap::Igncr,
+            RxMap::Igncr => RxMap::None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RxMap::None => "none",
+            RxMap::Crcrlf => "CR -> CRLF",
+            RxMap::Lfcrlf => "LF -> CRLF",
+            RxMap::Crlf => "CR -> LF",
+            RxMap::Igncr => "ignore CR",
+        }
+    }
+
+    pub fn apply<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        let (from, to): (u8, &[u8]) = match *self {
+            RxMap::None => return Cow::Borrowed(data),
+            RxMap::Crcrlf => (b'\r', b"\r\n"),
+            RxMap::Lfcrlf => (b'\n', b"\r\n"),
+            RxMap::Crlf => (b'\r', b"\n"),
+            RxMap::Igncr => (b'\r', b""),
+        };
+        if !data.contains(&from) {
+            return Cow::Borrowed(data);
+        }
+
+        let mut mapped = Vec::with_capacity(data.len() + 16);
+        for &byte in data {
+            if byte == from {
+                mapped.extend_from_slice(to);
+            } else {
+                mapped.push(byte);
+            }
+        }
+        Cow::Owned(mapped)
+    }
+}
+
+/// What the Enter key sends, like picocom's --omap for CR
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxEnter {
+    Cr,
+    Lf,
+    Crlf,
+}
+
+impl TxEnter {
+    pub fn next(&self) -> Self {
+        match *self {
+            TxEnter::Cr => TxEnter::Crlf,
+            TxEnter::Crlf => TxEnter::Lf,
+            TxEnter::Lf => TxEnter::Cr,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TxEnter::Cr => "CR",
+            TxEnter::Lf => "LF",
+            TxEnter::Crlf => "CRLF",
+        }
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        match *self {
+            TxEnter::Cr => b"\r",
+            TxEnter::Lf => b"\n",
+            TxEnter::Crlf => b"\r\n",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::newline::RxMap;
+
+    #[test]
+    fn rx_map() {
+        let data = b"a\rb\nc\r\n";
+        assert_eq!(RxMap::None.apply(data).as_ref(), data);
+        assert_eq!(RxMap::Crcrlf.apply(data).as_ref(), b"a\r\nb\nc\r\n\n");
+        assert_eq!(RxMap::Lfcrlf.apply(data).as_ref(), b"a\rb\r\nc\r\r\n");
+        assert_eq!(RxMap::Crlf.apply(data).as_ref(), b"a\nb\nc\n\n");
+        assert_eq!(RxMap::Igncr.apply(data).as_ref(), b"ab\nc\n");
+        assert_eq!(RxMap::Igncr.apply(b"\r").as_ref(), b"");
+
+        /* Byte by byte, so a CRLF split over two reads maps the same */
+        for map in [RxMap::None, RxMap::Crcrlf, RxMap::Lfcrlf, RxMap::Crlf, RxMap::Igncr] {
+            let split: Vec<u8> = [&data[..6], &data[6..]].iter().flat_map(|part| map.apply(part).into_owned()).collect();
+            assert_eq!(split, map.apply(data).as_ref());
+        }
+    }
+}
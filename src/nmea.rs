@@ -0,0 +1,303 @@
+use crossterm::style::{Attribute, Color, ContentStyle};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/* Longer lines can't be NMEA, pass them on */
+const MAX_SENTENCE: usize = 1024;
+
+/* Appended to sentences with a wrong checksum */
+const BAD_CHECKSUM: &str = "<- bad checksum";
+
+/// Checks the NMEA 0183 sentences in the received lines and keeps the GNSS
+/// status from GGA, RMC and GSV
+#[derive(Default)]
+pub struct NmeaDecoder {
+    line: Vec<u8>,
+    /* The current line doesn't start with $, it is shown as it arrives */
+    passthrough: bool,
+    /* UTC time and date */
+    time: Option<String>,
+    date: Option<String>,
+    quality: Option<u8>,
+    /* RMC status, A(ctive) or V(oid) */
+    valid: Option<bool>,
+    used: Option<u32>,
+    /* Satellites in view per talker, GP, GL, GA, ... */
+    in_view: BTreeMap<String, u32>,
+    position: Option<(f64, f64)>,
+    altitude: Option<f64>,
+    hdop: Option<f64>,
+    speed: Option<f64>,
+    good: u64,
+    bad: u64,
+    /* The panel needs to be drawn again */
+    changed: bool,
+}
+
+/// Sentence checksum, XOR of everything between $ and *
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |sum, byte| sum ^ byte)
+}
+
+/// Highlight rule for the sentences with a wrong checksum
+pub fn bad_checksum_rule() -> (Regex, ContentStyle) {
+    let regex = Regex::new(&format!(r"^\$.*{}.*", regex::escape(BAD_CHECKSUM))).unwrap();
+    let mut style = ContentStyle::new();
+    style.foreground_color = Some(Color::Red);
+    style.attributes.set(Attribute::Reverse);
+    (regex, style)
+}
+
+fn quality_name(quality: u8) -> &'static str {
+    match quality {
+        0 => "no fix",
+        1 => "GPS",
+        2 => "DGPS",
+        3 => "PPS",
+        4 => "RTK fixed",
+        5 => "RTK float",
+        6 => "estimated",
+        7 => "manual",
+        8 => "simulation",
+        _ => "unknown",
+    }
+}
+
+/* ddmm.mmmm (dddmm.mmmm for longitudes) and the hemisphere to degrees */
+fn parse_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let dot = value.find('.').unwrap_or(value.len());
+    if dot < 2 {
+        return None;
+    }
+    let degrees: f64 = value.get(..dot - 2)?.parse().ok()?;
+    let minutes: f64 = value.get(dot - 2..)?.parse().ok()?;
+    let coordinate = degrees + minutes / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(coordinate),
+        "S" | "W" => Some(-coordinate),
+        _ => None,
+    }
+}
+
+fn parse_time(value: &str) -> Option<String> {
+    let digits = value.get(..6)?;
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{}:{}:{}", &digits[..2], &digits[2..4], &digits[4..6]))
+}
+
+fn parse_date(value: &str) -> Option<String> {
+    if value.len() != 6 || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    /* Two digit years, GPS started in 1980 */
+    let century = if &value[4..6] >= "80" { "19" } else { "20" };
+    Some(format!("{}{}-{}-{}", century, &value[4..6], &value[2..4], &value[..2]))
+}
+
+impl NmeaDecoder {
+    /// Received data, returns the complete sentences in it with bad ones marked
+    /// and the other lines as they arrive, prompts have no line end
+    pub fn decode(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &byte in data {
+            /* Sentences start with $ and the talker, "$ " is a shell prompt */
+            let sentence = match self.line.len() {
+                0 => byte == b'$',
+                1 => byte.is_ascii_uppercase(),
+                _ => true,
+            };
+            if self.passthrough || !sentence {
+                self.passthrough = byte != b'\n';
+                out.append(&mut self.line);
+                out.push(byte);
+                continue;
+            }
+            self.line.push(byte);
+            if byte == b'\n' || self.line.len() > MAX_SENTENCE {
+                let line = std::mem::take(&mut self.line);
+                out.extend(self.check_line(&line));
+            }
+        }
+        out
+    }
+
+    fn check_line(&mut self, line: &[u8]) -> Vec<u8> {
+        let Ok(text) = std::str::from_utf8(line) else {
+            return line.to_vec();
+        };
+        let sentence = text.trim_end_matches(['\r', '\n']);
+        let Some(body) = sentence.strip_prefix('$') else {
+            return line.to_vec();
+        };
+
+        let (body, error) = match body.split_once('*') {
+            Some((body, sum)) => {
+                let expected = checksum(body);
+                match u8::from_str_radix(sum, 16) {
+                    Ok(sum) if sum == expected => (body, None),
+                    _ => (body, Some(format!("{}, expected {:02X}", BAD_CHECKSUM, expected))),
+                }
+            }
+            None => (body, Some(format!("{}, missing", BAD_CHECKSUM))),
+        };
+
+        self.changed = true;
+        match error {
+            Some(error) => {
+                self.bad += 1;
+                /* Keep the line end, the RX newline mapping still applies */
+                format!("{}  {}{}", sentence, error, &text[sentence.len()..]).into_bytes()
+            }
+            None => {
+                self.good += 1;
+                self.parse_sentence(body);
+                line.to_vec()
+            }
+        }
+    }
+
+    fn parse_sentence(&mut self, body: &str) {
+        let fields: Vec<&str> = body.split(',').collect();
+        let address = fields[0];
+        if address.len() != 5 || !address.is_ascii() {
+            return;
+        }
+        let field = |idx: usize| fields.get(idx).copied().unwrap_or("");
+        let (talker, kind) = address.split_at(2);
+
+        match kind {
+            "GGA" => {
+                self.time = parse_time(field(1)).or(self.time.take());
+                self.position = parse_coordinate(field(2), field(3)).zip(parse_coordinate(field(4), field(5)));
+                self.quality = field(6).parse().ok();
+                self.used = field(7).parse().ok();
+                self.hdop = field(8).parse().ok();
+                self.altitude = field(9).parse().ok();
+            }
+            "RMC" => {
+                self.time = parse_time(field(1)).or(self.time.take());
+                self.valid = Some(field(2) == "A");
+                if let Some(position) = parse_coordinate(field(3), field(4)).zip(parse_coordinate(field(5), field(6))) {
+                    self.position = Some(position);
+                }
+                self.speed = field(7).parse().ok();
+                self.date = parse_date(field(9)).or(self.date.take());
+            }
+            "GSV" => {
+                if let Ok(count) = field(3).parse() {
+                    self.in_view.insert(talker.to_string(), count);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether the status changed since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// The status for the panel
+    pub fn status_lines(&self) -> Vec<String> {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        let fix = match (self.quality, self.valid) {
+            (Some(quality), _) => format!("{} ({})", quality_name(quality), quality),
+            (None, Some(true)) => "valid".to_string(),
+            (None, Some(false)) => "no fix".to_string(),
+            (None, None) => "-".to_string(),
+        };
+        let in_view: u32 = self.in_view.values().sum();
+        let per_talker: Vec<String> =
+            self.in_view.iter().map(|(talker, count)| format!("{} {}", talker, count)).collect();
+        let satellites = format!(
+            "{} used, {} in view{}",
+            or_dash(self.used.map(|used| used.to_string())),
+            in_view,
+            match per_talker.is_empty() {
+                true => String::new(),
+                false => format!(" ({})", per_talker.join(", ")),
+            }
+        );
+
+        let position = self.position.map(|(lat, lon)| {
+            format!(
+                "{:.6} {}  {:.6} {}",
+                lat.abs(),
+                if lat < 0.0 { 'S' } else { 'N' },
+                lon.abs(),
+                if lon < 0.0 { 'W' } else { 'E' }
+            )
+        });
+        let time = match (&self.date, &self.time) {
+            (Some(date), Some(time)) => Some(format!("{} {} UTC", date, time)),
+            (None, Some(time)) => Some(format!("{} UTC", time)),
+            _ => None,
+        };
+
+        vec![
+            format!(
+                "Fix: {}   Satellites: {}   HDOP: {}",
+                fix,
+                satellites,
+                or_dash(self.hdop.map(|hdop| hdop.to_string()))
+            ),
+            format!(
+                "Position: {}   Altitude: {}   Speed: {}",
+                or_dash(position),
+                or_dash(self.altitude.map(|altitude| format!("{} m", altitude))),
+                or_dash(self.speed.map(|speed| format!("{} kn", speed)))
+            ),
+            format!("Time: {}   Sentences: {} ok, {} bad", or_dash(time), self.good, self.bad),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nmea::NmeaDecoder;
+
+    #[test]
+    fn sentences() {
+        let mut nmea = NmeaDecoder::default();
+        let gga = b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+
+        /* Good sentences and other lines pass unchanged, once complete */
+        assert_eq!(nmea.decode(&gga[..20]), b"");
+        assert_eq!(nmea.decode(&gga[20..]), gga);
+        assert_eq!(nmea.decode(b"boot ok\r\n"), b"boot ok\r\n");
+        assert_eq!(
+            nmea.decode(b"$GPGSV,3,1,11,03,03,111,00,04,15,270,00,06,01,010,00,13,06,292,00*74\r\n$GLGSV,1,1,02*7A\r\n"),
+            b"$GPGSV,3,1,11,03,03,111,00,04,15,270,00,06,01,010,00,13,06,292,00*74\r\n$GLGSV,1,1,02*7A  <- bad checksum, expected 67\r\n"
+        );
+
+        let lines = nmea.status_lines();
+        assert_eq!(lines[0], "Fix: GPS (1)   Satellites: 8 used, 11 in view (GP 11)   HDOP: 0.9");
+        assert_eq!(lines[1], "Position: 48.117300 N  11.516667 E   Altitude: 545.4 m   Speed: -");
+        assert_eq!(lines[2], "Time: 12:35:19 UTC   Sentences: 2 ok, 1 bad");
+    }
+
+    #[test]
+    fn other_data() {
+        let mut nmea = NmeaDecoder::default();
+
+        /* A prompt shows up before its line ends */
+        assert_eq!(nmea.decode(b"\r\nlogin: "), b"\r\nlogin: ");
+        assert_eq!(nmea.decode(b"root\r\n$GPGSA"), b"root\r\n");
+        assert_eq!(nmea.decode(b"*42\r\n"), b"$GPGSA*42\r\n");
+        assert_eq!(nmea.decode(b"$"), b"");
+        assert_eq!(nmea.decode(b" ls"), b"$ ls");
+        assert_eq!(nmea.decode(b"\r\n$ "), b"\r\n$ ");
+
+        /* Non-ASCII where ASCII is expected */
+        let body = "a\u{e9}bc";
+        let sentence = format!("${}*{:02X}\r\n", body, crate::nmea::checksum(body));
+        assert_eq!(nmea.decode(sentence.as_bytes()), sentence.as_bytes());
+        let body = "GPGGA,123519,\u{e9}5.0,N,01131.000,E,1";
+        let sentence = format!("${}*{:02X}\r\n", body, crate::nmea::checksum(body));
+        assert_eq!(nmea.decode(sentence.as_bytes()), sentence.as_bytes());
+        assert!(nmea.status_lines()[1].starts_with("Position: -"));
+    }
+}
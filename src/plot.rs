@@ -0,0 +1,246 @@
+use crate::decoder::MAX_LINE;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use tokio::time::Instant;
+
+/* Where the numbers to plot come from, in the config file:
+ *
+ *   [plot]
+ *   regex = 'T=([-\d.]+) P=(?P<pressure>[-\d.]+)'   a series per capture group, named groups name it
+ *   columns = [2, 3]                                or CSV columns, counting from 1
+ *   separator = ";"                                 between the columns, "," when not set
+ *   window = 120                                    seconds shown, 60 when not set
+ *
+ * Without a regex or columns every name=value (or name: value) on a line is a
+ * series, e.g. "temp=23.4,hum=41". */
+
+/* Keep at most this many points per series */
+const MAX_POINTS: usize = 10000;
+
+/* Names seen after this many are ignored, a device sending many different
+ * names would otherwise grow the list without bound */
+const MAX_SERIES: usize = 16;
+
+const PAIRS: &str = r"([A-Za-z_][\w.]*)\s*[=:]\s*(-?\d+(?:\.\d+)?(?:[eE][-+]?\d+)?)";
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlotConfig {
+    regex: Option<String>,
+    columns: Option<Vec<usize>>,
+    separator: Option<String>,
+    window: Option<u64>,
+}
+
+enum Extractor {
+    /* A series per capture group, with the name of the group (or its number) */
+    Regex(Regex, Vec<String>),
+    Columns(Vec<usize>, String),
+    Pairs(Regex),
+}
+
+/// The values of one name, as seconds since the start and value
+pub struct Series {
+    pub name: String,
+    pub points: VecDeque<(f64, f64)>,
+}
+
+/// Takes the numbers out of the received lines and keeps them for the plot view
+pub struct Plot {
+    extractor: Extractor,
+    series: Vec<Series>,
+    window: f64,
+    start: Instant,
+    line: String,
+}
+
+impl Plot {
+    /// `regex` and `columns` from the command line win over the config
+    pub fn new(config: Option<&PlotConfig>, regex: Option<&str>, columns: Option<&[usize]>) -> Result<Plot> {
+        let default = PlotConfig::default();
+        let config = config.unwrap_or(&default);
+        let regex = regex.or(config.regex.as_deref());
+        let columns = columns.or(config.columns.as_deref());
+
+        let extractor = match (regex, columns) {
+            (Some(regex), _) => {
+                let regex = Regex::new(regex).with_context(|| format!("Invalid plot regex '{}'", regex))?;
+                let names: Vec<String> = match regex.captures_len() {
+                    1 => vec!["value".to_string()],
+                    _ => regex
+                        .capture_names()
+                        .enumerate()
+                        .skip(1)
+                        .map(|(idx, name)| name.map(str::to_string).unwrap_or_else(|| idx.to_string()))
+                        .collect(),
+                };
+                Extractor::Regex(regex, names)
+            }
+            (None, Some(columns)) => {
+                if columns.contains(&0) {
+                    bail!("Plot columns count from 1");
+                }
+                let separator = config.separator.clone().unwrap_or_else(|| ",".to_string());
+                Extractor::Columns(columns.to_vec(), separator)
+            }
+            (None, None) => Extractor::Pairs(Regex::new(PAIRS).unwrap()),
+        };
+
+        Ok(Plot {
+            extractor,
+            series: Vec::new(),
+            window: config.window.unwrap_or(60).max(1) as f64,
+            start: Instant::now(),
+            line: String::new(),
+        })
+    }
+
+    /// Received data, the values of every complete line are added at `now`
+    pub fn feed(&mut self, data: &[u8], now: Instant) {
+        self.line.push_str(&String::from_utf8_lossy(data));
+        while let Some(end) = self.line.find('\n') {
+            let line: String = self.line.drain(..=end).collect();
+            let time = self.time(now);
+            /* "nan", "inf" and huge exponents parse but can't be plotted */
+            for (name, value) in self.extract(&line).into_iter().filter(|(_, value)| value.is_finite()) {
+                self.add(&name, time, value);
+            }
+        }
+        if self.line.len() > MAX_LINE {
+            self.line.clear();
+        }
+
+        /* Forget what scrolled out of the window */
+        let oldest = self.time(now) - self.window;
+        for series in &mut self.series {
+            while series.points.front().is_some_and(|&(time, _)| time < oldest) {
+                series.points.pop_front();
+            }
+        }
+    }
+
+    fn extract(&self, line: &str) -> Vec<(String, f64)> {
+        match &self.extractor {
+            Extractor::Regex(regex, names) => {
+                let Some(captures) = regex.captures(line) else {
+                    return Vec::new();
+                };
+                /* Without groups the whole match is the value */
+                let skip = usize::from(captures.len() > 1);
+                captures
+                    .iter()
+                    .skip(skip)
+                    .zip(names)
+                    .filter_map(|(group, name)| Some((name.clone(), group?.as_str().trim().parse().ok()?)))
+                    .collect()
+            }
+            Extractor::Columns(columns, separator) => {
+                let fields: Vec<&str> = line.split(separator.as_str()).collect();
+                columns
+                    .iter()
+                    .filter_map(|&column| {
+                        let value = fields.get(column - 1)?.trim().parse().ok()?;
+                        Some((format!("column {}", column), value))
+                    })
+                    .collect()
+            }
+            Extractor::Pairs(regex) => regex
+                .captures_iter(line)
+                .filter_map(|captures| Some((captures[1].to_string(), captures[2].parse().ok()?)))
+                .collect(),
+        }
+    }
+
+    fn add(&mut self, name: &str, time: f64, value: f64) {
+        let idx = match self.series.iter().position(|series| series.name == name) {
+            Some(idx) => idx,
+            None if self.series.len() == MAX_SERIES => return,
+            None => {
+                self.series.push(Series {
+                    name: name.to_string(),
+                    points: VecDeque::new(),
+                });
+                self.series.len() - 1
+            }
+        };
+        let points = &mut self.series[idx].points;
+        if points.len() == MAX_POINTS {
+            points.pop_front();
+        }
+        points.push_back((time, value));
+    }
+
+    fn time(&self, now: Instant) -> f64 {
+        (now - self.start).as_secs_f64()
+    }
+
+    /// Seconds since the start, the right end of the plot
+    pub fn now(&self) -> f64 {
+        self.time(Instant::now())
+    }
+
+    /// Seconds shown
+    pub fn window(&self) -> f64 {
+        self.window
+    }
+
+    pub fn series(&self) -> &[Series] {
+        &self.series
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plot::{Plot, MAX_SERIES};
+    use tokio::time::{Duration, Instant};
+
+    fn values(plot: &Plot) -> Vec<(String, Vec<f64>)> {
+        plot.series()
+            .iter()
+            .map(|series| (series.name.clone(), series.points.iter().map(|&(_, value)| value).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn extract() {
+        let now = Instant::now();
+
+        let mut plot = Plot::new(None, None, None).unwrap();
+        plot.feed(b"temp=23.4,hum=41\r\ntemp=2", now);
+        plot.feed(b"3.5, hum: -4e1\n", now);
+        assert_eq!(
+            values(&plot),
+            [("temp".to_string(), vec![23.4, 23.5]), ("hum".to_string(), vec![41.0, -40.0])]
+        );
+
+        let mut plot = Plot::new(None, Some(r"T=(\S+) P=(?P<p>\S+)"), None).unwrap();
+        plot.feed(b"T=1.5 P=1013\nT=x P=1014\nboot\n", now);
+        assert_eq!(values(&plot), [("1".to_string(), vec![1.5]), ("p".to_string(), vec![1013.0, 1014.0])]);
+
+        let mut plot = Plot::new(None, None, Some(&[3])).unwrap();
+        plot.feed(b"12,a, 7.25\n", now);
+        assert_eq!(values(&plot), [("column 3".to_string(), vec![7.25])]);
+
+        /* Old points go */
+        plot.feed(b"1,2,3\n", now + Duration::from_secs(61));
+        assert_eq!(values(&plot), [("column 3".to_string(), vec![3.0])]);
+    }
+
+    #[test]
+    fn limits() {
+        let now = Instant::now();
+
+        let mut plot = Plot::new(None, Some(r"v=(\S+)"), None).unwrap();
+        plot.feed(b"v=nan\nv=inf\nv=-infinity\nv=1e999\nv=2\n", now);
+        assert_eq!(values(&plot), [("1".to_string(), vec![2.0])]);
+
+        let mut plot = Plot::new(None, None, None).unwrap();
+        for idx in 0..100 {
+            plot.feed(format!("name{}=1\n", idx).as_bytes(), now);
+        }
+        assert_eq!(plot.series().len(), MAX_SERIES);
+        assert_eq!(plot.series().last().unwrap().name, format!("name{}", MAX_SERIES - 1));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::escape::unescape;
+use crate::script::{trim_rx, MAX_RX_BUFFER};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use regex::Regex;
+use std::{fs::File, path::PathBuf, process::ExitCode};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::{timeout_at, Duration, Instant},
+};
+use tokio_serial::SerialStream;
+
+/// Exit codes of the run command
+pub const EXIT_MATCHED: u8 = 0;
+pub const EXIT_FAILED: u8 = 1;
+pub const EXIT_TIMEOUT: u8 = 2;
+pub const EXIT_ERROR: u8 = 3;
+
+/// Send strings and wait for a pattern, for CI.
+/// Exits with 0 on a match, 1 on a failure pattern, 2 on a timeout and 3 on errors.
+#[derive(Args)]
+pub struct RunArgs {
+    /// Send STRING once the port is open, C-style escapes are allowed (can be repeated)
+    #[arg(long, value_name = "STRING")]
+    send: Vec<String>,
+
+    /// Regex that means success
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    expect: Regex,
+
+    /// Regex that means failure (can be repeated)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    fail_on: Vec<Regex>,
+
+    /// Give up after this many seconds
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    timeout: u64,
+
+    /// Also write everything received to FILE
+    #[arg(long, value_name = "FILE")]
+    log: Option<PathBuf>,
+}
+
+enum Outcome {
+    Matched(String),
+    Failed(String),
+    Timeout,
+}
+
+pub async fn run(port: &mut SerialStream, args: &RunArgs) -> ExitCode {
+    match run_until_done(port, args).await {
+        Ok(Outcome::Matched(text)) => {
+            eprintln!("\nminircom: matched '{}'", text);
+            ExitCode::from(EXIT_MATCHED)
+        }
+        Ok(Outcome::Failed(text)) => {
+            eprintln!("\nminircom: failure pattern matched '{}'", text);
+            ExitCode::from(EXIT_FAILED)
+        }
+        Ok(Outcome::Timeout) => {
+            eprintln!("\nminircom: timeout after {} seconds", args.timeout);
+            ExitCode::from(EXIT_TIMEOUT)
+        }
+        Err(e) => {
+            eprintln!("\nminircom: {:#}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+async fn run_until_done(port: &mut SerialStream, args: &RunArgs) -> Result<Outcome> {
+    let deadline = Instant::now() + Duration::from_secs(args.timeout);
+    let mut log = match &args.log {
+        Some(path) => Some(
+            File::create(path).with_context(|| format!("Could not create {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let mut stdout = tokio::io::stdout();
+
+    for str in &args.send {
+        port.write_all(&unescape(str)?).await?;
+    }
+
+    let mut buf: [u8; 128] = [0; 128];
+    let mut rx = String::new();
+    loop {
+        let read_bytes = match timeout_at(deadline, port.read(&mut buf)).await {
+            Ok(read_bytes) => read_bytes.context("Could not read from the port")?,
+            Err(_) => return Ok(Outcome::Timeout),
+        };
+        if read_bytes == 0 {
+            bail!("Port closed");
+        }
+
+        let data = &buf[0..read_bytes];
+        stdout.write_all(data).await?;
+        stdout.flush().await?;
+        if let Some(log) = log.as_mut() {
+            std::io::Write::write_all(log, data)?;
+        }
+
+        rx.push_str(&String::from_utf8_lossy(data));
+        for regex in &args.fail_on {
+            if let Some(found) = regex.find(&rx) {
+                return Ok(Outcome::Failed(found.as_str().to_string()));
+            }
+        }
+        if let Some(found) = args.expect.find(&rx) {
+            return Ok(Outcome::Matched(found.as_str().to_string()));
+        }
+
+        trim_rx(&mut rx, MAX_RX_BUFFER);
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use std::process::Stdio;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::mpsc,
+};
+
+/* Reads waiting for the filter to take them, after that data is dropped */
+const MAX_PENDING_WRITES: usize = 256;
+
+/// Output of filter `id`, empty when it exited
+pub type FilterOutput = (u32, Vec<u8>);
+
+/// External command the received data is piped through, its output is
+/// what gets shown.
+pub struct RxFilter {
+    id: u32,
+    cmd: String,
+    to_stdin: mpsc::Sender<Vec<u8>>,
+    /* Killed when the filter is dropped, with the rest of its process group */
+    child: Child,
+}
+
+async fn forward_output(
+    mut out: impl AsyncRead + Unpin,
+    output: mpsc::Sender<FilterOutput>,
+    id: u32,
+    eof: bool,
+) {
+    let mut buf: [u8; 1024] = [0; 1024];
+    loop {
+        match out.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(len) => {
+                if output.send((id, buf[..len].to_vec())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    if eof {
+        let _ = output.send((id, Vec::new())).await;
+    }
+}
+
+impl RxFilter {
+    /// Run `cmd` with the shell, its stdout and stderr go to `output`
+    pub fn spawn(id: u32, cmd: &str, output: mpsc::Sender<FilterOutput>) -> Result<RxFilter> {
+        #[cfg(unix)]
+        let mut command = {
+            use std::os::unix::process::CommandExt;
+            /* A group of its own, so a whole pipeline can be stopped */
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").process_group(0);
+            Command::from(command)
+        };
+        #[cfg(windows)]
+        let mut command = Command::new("cmd");
+        #[cfg(windows)]
+        command.arg("/C");
+
+        let mut child = command
+            .arg(cmd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Could not run '{}'", cmd))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let (to_stdin, mut from_app) = mpsc::channel::<Vec<u8>>(MAX_PENDING_WRITES);
+        tokio::spawn(async move {
+            while let Some(data) = from_app.recv().await {
+                if stdin.write_all(&data).await.is_err() || stdin.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+        tokio::spawn(forward_output(stdout, output.clone(), id, true));
+        tokio::spawn(forward_output(stderr, output, id, false));
+
+        Ok(RxFilter {
+            id,
+            cmd: cmd.to_string(),
+            to_stdin,
+            child,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn cmd(&self) -> &str {
+        &self.cmd
+    }
+
+    /// Returns false when the data was dropped, as the filter doesn't keep up
+    pub fn write(&self, data: &[u8]) -> bool {
+        match self.to_stdin.try_send(data.to_vec()) {
+            Err(mpsc::error::TrySendError::Full(_)) => false,
+            /* When it exited the output channel tells */
+            Ok(()) | Err(mpsc::error::TrySendError::Closed(_)) => true,
+        }
+    }
+}
+
+impl Drop for RxFilter {
+    fn drop(&mut self) {
+        /* kill_on_drop only gets the shell */
+        #[cfg(unix)]
+        if let Some(pid) = self.child.id() {
+            // SAFETY: kill() has no memory safety requirements
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
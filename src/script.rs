@@ -0,0 +1,417 @@
+use crate::escape::unescape;
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use std::{collections::HashMap, path::Path};
+use tokio::time::{Duration, Instant};
+
+/* Script language, one command per line, '#' starts a comment:
+ *
+ *   start:                      label
+ *   send "setenv foo bar\r"     send a string, C-style escapes are allowed
+ *   expect "=> " timeout 5      wait for a regex (default timeout 10 seconds)
+ *   if timeout goto start       jump when the last expect timed out
+ *   if matched goto done        jump when the last expect matched
+ *   goto start
+ *   sleep 0.5                   wait, in seconds
+ *   set baud 9600               change the baud rate of the port
+ *   log "booting kernel"        print a message on the screen
+ *   exit                        stop the script
+ */
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/* Commands run in one go before the script is considered stuck in a loop */
+const MAX_STEPS: usize = 1000;
+
+/// Things the script wants the app to do
+pub enum Action {
+    Send(Vec<u8>),
+    SetBaud(u32),
+    Log(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Condition {
+    Matched,
+    Timeout,
+}
+
+enum Instr {
+    Send(Vec<u8>),
+    Expect(Regex, Duration),
+    Sleep(Duration),
+    Goto(usize),
+    IfGoto(Condition, usize),
+    SetBaud(u32),
+    Log(String),
+    Exit,
+}
+
+enum Wait {
+    None,
+    Expect(Instant),
+    Sleep(Instant),
+}
+
+pub struct Script {
+    name: String,
+    instrs: Vec<Instr>,
+    pc: usize,
+    wait: Wait,
+    matched: bool,
+    rx: String,
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+}
+impl Token {
+    fn as_str(&self) -> &str {
+        match self {
+            Token::Word(s) | Token::Quoted(s) => s,
+        }
+    }
+}
+
+/// Split a line in words and quoted strings, the content of quoted
+/// strings is kept as is (except for \") so regexes keep their escapes.
+fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut str = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        str.push('"');
+                    }
+                    Some('\\') => {
+                        str.push('\\');
+                        if let Some(c) = chars.next() {
+                            str.push(c);
+                        }
+                    }
+                    Some(c) => str.push(c),
+                    None => bail!("missing closing \""),
+                }
+            }
+            tokens.push(Token::Quoted(str));
+        } else {
+            let mut str = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                str.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(str));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Received text kept for matching by expect, triggers and the run subcommand
+pub const MAX_RX_BUFFER: usize = 16 * 1024;
+
+/// Drop the start of received text kept for matching, so at most `max`
+/// bytes remain
+pub fn trim_rx(rx: &mut String, max: usize) {
+    if rx.len() > max {
+        let mut cut = rx.len() - max;
+        while !rx.is_char_boundary(cut) {
+            cut += 1;
+        }
+        rx.drain(..cut);
+    }
+}
+
+fn parse_seconds(str: &str) -> Result<Duration> {
+    let secs: f64 = str
+        .parse()
+        .map_err(|_| anyhow!("invalid number of seconds '{}'", str))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| anyhow!("invalid number of seconds '{}'", str))
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script> {
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read script {}", path.display()))?;
+        Script::parse(&path.display().to_string(), &src)
+    }
+
+    pub fn parse(name: &str, src: &str) -> Result<Script> {
+        let mut labels = HashMap::new();
+        /* Instructions with the label they jump to, resolved at the end */
+        let mut parsed: Vec<(Instr, Option<(usize, String)>)> = Vec::new();
+
+        for (idx, line) in src.lines().enumerate() {
+            let line_nr = idx + 1;
+            let tokens = tokenize(line).with_context(|| format!("{}:{}", name, line_nr))?;
+            let instr = parse_instr(&tokens, &mut labels, parsed.len())
+                .with_context(|| format!("{}:{}", name, line_nr))?;
+            if let Some((instr, label)) = instr {
+                parsed.push((instr, label.map(|l| (line_nr, l))));
+            }
+        }
+
+        let mut instrs = Vec::with_capacity(parsed.len());
+        for (instr, label) in parsed {
+            let instr = match label {
+                Some((line_nr, label)) => {
+                    let target = *labels
+                        .get(&label)
+                        .ok_or_else(|| anyhow!("{}:{}: unknown label '{}'", name, line_nr, label))?;
+                    match instr {
+                        Instr::Goto(_) => Instr::Goto(target),
+                        Instr::IfGoto(cond, _) => Instr::IfGoto(cond, target),
+                        _ => unreachable!(),
+                    }
+                }
+                None => instr,
+            };
+            instrs.push(instr);
+        }
+
+        Ok(Script {
+            name: name.to_string(),
+            instrs,
+            pc: 0,
+            wait: Wait::None,
+            matched: false,
+            rx: String::new(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Received serial data, used by expect
+    pub fn feed(&mut self, data: &[u8]) {
+        self.rx.push_str(&String::from_utf8_lossy(data));
+        trim_rx(&mut self.rx, MAX_RX_BUFFER);
+    }
+
+    /// Run until the script has to wait, returns the actions to perform
+    /// and whether the script is finished.
+    pub fn run(&mut self, now: Instant) -> Result<(Vec<Action>, bool)> {
+        let mut actions = Vec::new();
+
+        for _ in 0..MAX_STEPS {
+            let Some(instr) = self.instrs.get(self.pc) else {
+                return Ok((actions, true));
+            };
+
+            match instr {
+                Instr::Send(data) => actions.push(Action::Send(data.clone())),
+                Instr::Expect(regex, timeout) => {
+                    let deadline = match self.wait {
+                        Wait::Expect(deadline) => deadline,
+                        _ => now + *timeout,
+                    };
+
+                    if let Some(found) = regex.find(&self.rx) {
+                        let end = found.end();
+                        self.rx.drain(..end);
+                        self.matched = true;
+                    } else if now >= deadline {
+                        self.matched = false;
+                    } else {
+                        self.wait = Wait::Expect(deadline);
+                        return Ok((actions, false));
+                    }
+                    self.wait = Wait::None;
+                }
+                Instr::Sleep(duration) => {
+                    let deadline = match self.wait {
+                        Wait::Sleep(deadline) => deadline,
+                        _ => now + *duration,
+                    };
+                    if now < deadline {
+                        self.wait = Wait::Sleep(deadline);
+                        return Ok((actions, false));
+                    }
+                    self.wait = Wait::None;
+                }
+                Instr::Goto(target) => {
+                    self.pc = *target;
+                    continue;
+                }
+                Instr::IfGoto(cond, target) => {
+                    let taken = match cond {
+                        Condition::Matched => self.matched,
+                        Condition::Timeout => !self.matched,
+                    };
+                    if taken {
+                        self.pc = *target;
+                        continue;
+                    }
+                }
+                Instr::SetBaud(baud) => actions.push(Action::SetBaud(*baud)),
+                Instr::Log(msg) => actions.push(Action::Log(msg.clone())),
+                Instr::Exit => return Ok((actions, true)),
+            }
+            self.pc += 1;
+        }
+        bail!("{}: script keeps looping without waiting", self.name)
+    }
+}
+
+type ParsedInstr = Option<(Instr, Option<String>)>;
+
+fn parse_instr(
+    tokens: &[Token],
+    labels: &mut HashMap<String, usize>,
+    pc: usize,
+) -> Result<ParsedInstr> {
+    let Some(first) = tokens.first() else {
+        return Ok(None);
+    };
+    let args: Vec<&str> = tokens[1..].iter().map(|t| t.as_str()).collect();
+
+    let cmd = match first {
+        Token::Word(word) => word.as_str(),
+        Token::Quoted(_) => bail!("expected a command"),
+    };
+
+    if let Some(label) = cmd.strip_suffix(':') {
+        if label.is_empty() || !args.is_empty() {
+            bail!("invalid label");
+        }
+        if labels.insert(label.to_string(), pc).is_some() {
+            bail!("duplicate label '{}'", label);
+        }
+        return Ok(None);
+    }
+
+    let instr = match (cmd, args.as_slice()) {
+        ("send", [str]) => (Instr::Send(unescape(str)?), None),
+        ("expect", [regex]) => (Instr::Expect(Regex::new(regex)?, DEFAULT_TIMEOUT), None),
+        ("expect", [regex, "timeout", secs]) => {
+            (Instr::Expect(Regex::new(regex)?, parse_seconds(secs)?), None)
+        }
+        ("sleep", [secs]) => (Instr::Sleep(parse_seconds(secs)?), None),
+        ("goto", [label]) => (Instr::Goto(0), Some(label.to_string())),
+        ("if", [cond, "goto", label]) => {
+            let cond = match *cond {
+                "matched" => Condition::Matched,
+                "timeout" => Condition::Timeout,
+                _ => bail!("unknown condition '{}' (use matched or timeout)", cond),
+            };
+            (Instr::IfGoto(cond, 0), Some(label.to_string()))
+        }
+        ("set", ["baud", baud]) => {
+            let baud = baud
+                .parse()
+                .map_err(|_| anyhow!("invalid baud rate '{}'", baud))?;
+            (Instr::SetBaud(baud), None)
+        }
+        ("log", [msg]) => (Instr::Log(msg.to_string()), None),
+        ("exit", []) => (Instr::Exit, None),
+        ("send" | "expect" | "sleep" | "goto" | "if" | "set" | "log" | "exit", _) => {
+            bail!("invalid arguments for '{}'", cmd)
+        }
+        _ => bail!("unknown command '{}'", cmd),
+    };
+    Ok(Some(instr))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::{tokenize, trim_rx, Action, Script, Token};
+    use tokio::time::{Duration, Instant};
+
+    /* The sends and logs of a run, as text */
+    fn run(script: &mut Script, now: Instant) -> (Vec<String>, bool) {
+        let (actions, finished) = script.run(now).unwrap();
+        let actions = actions
+            .into_iter()
+            .map(|action| match action {
+                Action::Send(data) => format!("send {}", String::from_utf8_lossy(&data)),
+                Action::SetBaud(baud) => format!("baud {}", baud),
+                Action::Log(msg) => format!("log {}", msg),
+            })
+            .collect();
+        (actions, finished)
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize(r#"expect "\d+ \"x\"" timeout 5 # comment"#).unwrap();
+        assert!(
+            tokens
+                == [
+                    Token::Word("expect".to_string()),
+                    Token::Quoted(r#"\d+ "x""#.to_string()),
+                    Token::Word("timeout".to_string()),
+                    Token::Word("5".to_string()),
+                ]
+        );
+        assert!(tokenize(r#"send "open"#).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |src: &str| format!("{:#}", Script::parse("t", src).err().unwrap());
+        assert_eq!(error("send"), "t:1: invalid arguments for 'send'");
+        assert_eq!(error("\nfoo"), "t:2: unknown command 'foo'");
+        assert_eq!(error("goto nowhere"), "t:1: unknown label 'nowhere'");
+        assert_eq!(error("a:\na:"), "t:2: duplicate label 'a'");
+        assert_eq!(error("if found goto a\na:"), "t:1: unknown condition 'found' (use matched or timeout)");
+        assert_eq!(error("sleep -1"), "t:1: invalid number of seconds '-1'");
+        assert_eq!(error("set baud fast"), "t:1: invalid baud rate 'fast'");
+    }
+
+    #[test]
+    fn expect_and_jumps() {
+        let src = r#"
+            start:
+            send "x\r"
+            expect "=> " timeout 1
+            if timeout goto start
+            set baud 9600
+            sleep 0.5
+            log "done"
+        "#;
+        let mut script = Script::parse("t", src).unwrap();
+        let t0 = Instant::now();
+
+        assert_eq!(run(&mut script, t0), (vec!["send x\r".to_string()], false));
+        /* Timed out, sends again */
+        assert_eq!(run(&mut script, t0 + Duration::from_secs(1)), (vec!["send x\r".to_string()], false));
+
+        script.feed(b"U-Boot\r\n=");
+        assert_eq!(run(&mut script, t0 + Duration::from_secs(1)), (vec![], false));
+        script.feed(b"> ");
+        assert_eq!(run(&mut script, t0 + Duration::from_secs(1)), (vec!["baud 9600".to_string()], false));
+        assert_eq!(run(&mut script, t0 + Duration::from_millis(1400)), (vec![], false));
+        assert_eq!(run(&mut script, t0 + Duration::from_millis(1500)), (vec!["log done".to_string()], true));
+
+        let mut script = Script::parse("t", "a:\ngoto a").unwrap();
+        assert!(script.run(t0).is_err());
+    }
+
+    #[test]
+    fn trim() {
+        let mut rx = "abcdé".to_string();
+        trim_rx(&mut rx, 10);
+        assert_eq!(rx, "abcdé");
+        /* Never cuts a character in half */
+        trim_rx(&mut rx, 1);
+        assert_eq!(rx, "");
+        let mut rx = "abcdé".to_string();
+        trim_rx(&mut rx, 3);
+        assert_eq!(rx, "dé");
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Error, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::{broadcast, mpsc},
+    time::{sleep, Duration},
+};
+
+/* Number of serial reads buffered for a slow client before it starts to lose data */
+const CLIENT_BACKLOG: usize = 256;
+
+/* Pause after a failed accept, errors like EMFILE come back at once */
+const ACCEPT_RETRY_MS: u64 = 100;
+
+/// TCP server that shares the serial port with network clients (telnet/nc).
+/// Everything received on the serial port is sent to all clients,
+/// input from read-write clients is handed back to be sent to the port.
+pub struct Server {
+    from_clients: mpsc::Receiver<Vec<u8>>,
+    to_clients: broadcast::Sender<Vec<u8>>,
+}
+
+impl Server {
+    pub async fn bind(addr: &str, read_only: bool) -> Result<Server> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| Error::msg(format!("Could not listen on {} ({})", addr, e)))?;
+
+        let (in_tx, in_rx) = mpsc::channel(32);
+        let (out_tx, _) = broadcast::channel(CLIENT_BACKLOG);
+
+        tokio::spawn(accept_clients(listener, in_tx, out_tx.clone(), read_only));
+
+        Ok(Server {
+            from_clients: in_rx,
+            to_clients: out_tx,
+        })
+    }
+
+    /// Send received serial data to all connected clients
+    pub fn broadcast(&self, data: &[u8]) {
+        /* Fails when no clients are connected, that is fine */
+        let _ = self.to_clients.send(data.to_vec());
+    }
+
+    /// Wait for input from one of the read-write clients
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.from_clients.recv().await
+    }
+}
+
+async fn accept_clients(
+    listener: TcpListener,
+    in_tx: mpsc::Sender<Vec<u8>>,
+    out_tx: broadcast::Sender<Vec<u8>>,
+    read_only: bool,
+) {
+    loop {
+        /* Errors are per connection (e.g. too many open files), just try again */
+        match listener.accept().await {
+            Ok((stream, _addr)) => {
+                let in_tx = in_tx.clone();
+                let out_rx = out_tx.subscribe();
+                tokio::spawn(async move {
+                    /* A client going away is not our problem */
+                    let _ = handle_client(stream, in_tx, out_rx, read_only).await;
+                });
+            }
+            Err(_) => sleep(Duration::from_millis(ACCEPT_RETRY_MS)).await,
+        }
+    }
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    in_tx: mpsc::Sender<Vec<u8>>,
+    mut out_rx: broadcast::Receiver<Vec<u8>>,
+    read_only: bool,
+) -> Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut buf: [u8; 128] = [0; 128];
+
+    loop {
+        select! {
+            /* Serial data for the client */
+            data = out_rx.recv() => {
+                match data {
+                    Ok(data) => writer.write_all(&data).await?,
+                    /* Client is too slow, skip what it missed */
+                    Err(broadcast::error::RecvError::Lagged(_)) => (),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            /* Client input for the serial port */
+            read_bytes = reader.read(&mut buf) => {
+                match read_bytes? {
+                    0 => break,
+                    n => {
+                        if !read_only {
+                            in_tx.send(buf[0..n].to_vec()).await?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use tokio::time::{Duration, Instant};
+use tokio_serial::SerialStream;
+
+/// Line errors counted by the driver since the session started
+#[derive(Clone, Copy, Default)]
+pub struct LineErrors {
+    pub frame: u64,
+    pub parity: u64,
+    /* The UART lost bytes */
+    pub overrun: u64,
+    /* The tty buffer was full, the application didn't keep up */
+    pub buf_overrun: u64,
+    pub brk: u64,
+}
+
+impl LineErrors {
+    fn since(&self, base: &LineErrors) -> LineErrors {
+        LineErrors {
+            frame: self.frame.wrapping_sub(base.frame),
+            parity: self.parity.wrapping_sub(base.parity),
+            overrun: self.overrun.wrapping_sub(base.overrun),
+            buf_overrun: self.buf_overrun.wrapping_sub(base.buf_overrun),
+            brk: self.brk.wrapping_sub(base.brk),
+        }
+    }
+}
+
+/* The counters of the serial driver, not every driver has them (ptys and
+ * many USB adapters don't) */
+#[cfg(target_os = "linux")]
+fn read_line_errors(port: &SerialStream) -> Option<LineErrors> {
+    use std::os::raw::c_int;
+    use std::os::unix::io::AsRawFd;
+
+    /* struct serial_icounter_struct from linux/serial.h */
+    #[repr(C)]
+    #[derive(Default)]
+    struct Icounter {
+        cts: c_int,
+        dsr: c_int,
+        rng: c_int,
+        dcd: c_int,
+        rx: c_int,
+        tx: c_int,
+        frame: c_int,
+        overrun: c_int,
+        parity: c_int,
+        brk: c_int,
+        buf_overrun: c_int,
+        reserved: [c_int; 9],
+    }
+
+    let mut counter = Icounter::default();
+    // SAFETY: TIOCGICOUNT fills in a serial_icounter_struct, which Icounter mirrors
+    let ret = unsafe { libc::ioctl(port.as_raw_fd(), libc::TIOCGICOUNT, &mut counter as *mut Icounter) };
+    (ret == 0).then_some(LineErrors {
+        frame: counter.frame as u32 as u64,
+        parity: counter.parity as u32 as u64,
+        overrun: counter.overrun as u32 as u64,
+        buf_overrun: counter.buf_overrun as u32 as u64,
+        brk: counter.brk as u32 as u64,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_line_errors(_port: &SerialStream) -> Option<LineErrors> {
+    None
+}
+
+/* Bytes per second in one direction, measured over a second */
+#[derive(Default)]
+struct Rate {
+    bytes: u64,
+    current: f64,
+    peak: f64,
+}
+
+impl Rate {
+    fn update(&mut self, elapsed: Duration) {
+        self.current = self.bytes as f64 / elapsed.as_secs_f64();
+        self.peak = self.peak.max(self.current);
+        self.bytes = 0;
+    }
+}
+
+/// Counters of the session, to see whether bytes get lost
+pub struct Stats {
+    start: Instant,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_lines: u64,
+    rx_rate: Rate,
+    tx_rate: Rate,
+    rate_start: Instant,
+    /* Bytes per second the line can carry */
+    capacity: f64,
+    /* Driver counters at the start, None when the driver has none */
+    base: Option<LineErrors>,
+    errors: Option<LineErrors>,
+}
+
+pub fn format_rate(rate: f64) -> String {
+    match rate {
+        rate if rate >= 1e6 => format!("{:.1} MB/s", rate / 1e6),
+        rate if rate >= 1e3 => format!("{:.1} kB/s", rate / 1e3),
+        rate => format!("{:.0} B/s", rate),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn capacity(baud_rate: u32, bits_per_char: u32) -> f64 {
+    baud_rate as f64 / bits_per_char.max(1) as f64
+}
+
+impl Stats {
+    /// Call this when the port is opened, the line errors count from here.
+    /// `bits_per_char` includes the start, parity and stop bits
+    pub fn new(port: &SerialStream, baud_rate: u32, bits_per_char: u32) -> Stats {
+        let now = Instant::now();
+        Stats {
+            start: now,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            rx_lines: 0,
+            rx_rate: Rate::default(),
+            tx_rate: Rate::default(),
+            rate_start: now,
+            capacity: capacity(baud_rate, bits_per_char),
+            base: read_line_errors(port),
+            errors: None,
+        }
+    }
+
+    /// The line settings changed
+    pub fn set_line(&mut self, baud_rate: u32, bits_per_char: u32) {
+        self.capacity = capacity(baud_rate, bits_per_char);
+    }
+
+    pub fn received(&mut self, data: &[u8]) {
+        self.rx_bytes += data.len() as u64;
+        self.rx_rate.bytes += data.len() as u64;
+        self.rx_lines += data.iter().filter(|&&byte| byte == b'\n').count() as u64;
+    }
+
+    pub fn sent(&mut self, data: &[u8]) {
+        self.tx_bytes += data.len() as u64;
+        self.tx_rate.bytes += data.len() as u64;
+    }
+
+    /// Called every tick, the rates and error counts are updated every second
+    pub fn tick(&mut self, port: &SerialStream, now: Instant) {
+        let elapsed = now - self.rate_start;
+        if elapsed >= Duration::from_secs(1) {
+            self.rx_rate.update(elapsed);
+            self.tx_rate.update(elapsed);
+            self.rate_start = now;
+            self.update_errors(port);
+        }
+    }
+
+    fn update_errors(&mut self, port: &SerialStream) {
+        if let Some(base) = &self.base {
+            self.errors = read_line_errors(port).map(|errors| errors.since(base));
+        }
+    }
+
+    fn usage(&self, rate: f64) -> String {
+        match self.capacity > 0.0 {
+            true => format!("  ({:.0}% of the line)", rate * 100.0 / self.capacity),
+            false => String::new(),
+        }
+    }
+
+    /// Label and value of every counter, for the statistics screen
+    pub fn lines(&self) -> Vec<(String, String)> {
+        let mut lines = vec![
+            ("Session".to_string(), format_duration(self.start.elapsed())),
+            ("Received".to_string(), format!("{} bytes, {} lines", self.rx_bytes, self.rx_lines)),
+            ("Sent".to_string(), format!("{} bytes", self.tx_bytes)),
+            (
+                "RX throughput".to_string(),
+                format!(
+                    "{} now, {} peak{}",
+                    format_rate(self.rx_rate.current),
+                    format_rate(self.rx_rate.peak),
+                    self.usage(self.rx_rate.peak)
+                ),
+            ),
+            (
+                "TX throughput".to_string(),
+                format!(
+                    "{} now, {} peak{}",
+                    format_rate(self.tx_rate.current),
+                    format_rate(self.tx_rate.peak),
+                    self.usage(self.tx_rate.peak)
+                ),
+            ),
+            ("Line capacity".to_string(), format_rate(self.capacity)),
+        ];
+        match &self.errors.or(self.base.map(|_| LineErrors::default())) {
+            Some(errors) => lines.extend([
+                ("Framing errors".to_string(), errors.frame.to_string()),
+                ("Parity errors".to_string(), errors.parity.to_string()),
+                ("Overruns".to_string(), errors.overrun.to_string()),
+                ("Buffer overruns".to_string(), errors.buf_overrun.to_string()),
+                ("Breaks".to_string(), errors.brk.to_string()),
+            ]),
+            None => lines.push(("Line errors".to_string(), "not available for this port".to_string())),
+        }
+        lines
+    }
+
+    /// What is written to stderr on exit
+    pub fn summary(&mut self, port: &SerialStream) -> String {
+        self.update_errors(port);
+        let errors = match &self.errors {
+            Some(errors) => format!(
+                "{} framing, {} parity, {} overrun, {} buffer overrun, {} break",
+                errors.frame, errors.parity, errors.overrun, errors.buf_overrun, errors.brk
+            ),
+            None => "not available".to_string(),
+        };
+        format!(
+            "Session {}: received {} bytes ({} lines, peak {}), sent {} bytes (peak {})\nLine errors: {}",
+            format_duration(self.start.elapsed()),
+            self.rx_bytes,
+            self.rx_lines,
+            format_rate(self.rx_rate.peak),
+            self.tx_bytes,
+            format_rate(self.tx_rate.peak),
+            errors
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{format_rate, Rate};
+    use tokio::time::Duration;
+
+    #[test]
+    fn rates() {
+        let mut rate = Rate { bytes: 2304, ..Default::default() };
+        rate.update(Duration::from_millis(2000));
+        rate.bytes = 100;
+        rate.update(Duration::from_secs(1));
+        assert_eq!((rate.current, rate.peak, rate.bytes), (100.0, 1152.0, 0));
+
+        assert_eq!(format_rate(rate.current), "100 B/s");
+        assert_eq!(format_rate(rate.peak), "1.2 kB/s");
+        assert_eq!(format_rate(2.5e6), "2.5 MB/s");
+    }
+}
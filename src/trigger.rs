@@ -0,0 +1,246 @@
+use crate::escape::unescape;
+use crate::script::{trim_rx, MAX_RX_BUFFER};
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+/* Triggers in the config file, every matching trigger fires:
+ *
+ *   [[trigger]]
+ *   regex = 'Hit any key to stop autoboot'
+ *   send = ' '                  C-style escapes are allowed
+ *
+ *   [[trigger]]
+ *   regex = 'Kernel panic'
+ *   bell = true
+ *   marker = 'PANIC'            printed as a line of its own
+ *   capture = "start"           or "stop"
+ *   dtr = "toggle"              "on", "off" or "toggle"
+ *   run = 'notify-send panic'   local command, run with sh -c (cmd /C on windows)
+ *   once = true                 only fire the first time
+ */
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureAction {
+    Start,
+    Stop,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DtrAction {
+    On,
+    Off,
+    Toggle,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+    regex: String,
+    send: Option<String>,
+    #[serde(default)]
+    bell: bool,
+    capture: Option<CaptureAction>,
+    marker: Option<String>,
+    dtr: Option<DtrAction>,
+    run: Option<String>,
+    #[serde(default)]
+    once: bool,
+}
+
+/// Things a trigger wants the app to do
+#[derive(Clone)]
+pub enum TriggerAction {
+    Send(Vec<u8>),
+    Bell,
+    Capture(CaptureAction),
+    Marker(String),
+    Dtr(DtrAction),
+    Run(String),
+}
+
+struct Trigger {
+    regex: Regex,
+    actions: Vec<TriggerAction>,
+    once: bool,
+    fired: bool,
+    rx: String,
+}
+
+/// Watches the received data for the triggers from the config
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+}
+
+impl Triggers {
+    pub fn from_config(configs: &[TriggerConfig]) -> Result<Triggers> {
+        let mut triggers = Vec::new();
+
+        for config in configs {
+            let regex = Regex::new(&config.regex)
+                .with_context(|| format!("Invalid trigger regex '{}'", config.regex))?;
+
+            let mut actions = Vec::new();
+            if let Some(send) = &config.send {
+                let data = unescape(send)
+                    .with_context(|| format!("Invalid send for trigger '{}'", config.regex))?;
+                actions.push(TriggerAction::Send(data));
+            }
+            if config.bell {
+                actions.push(TriggerAction::Bell);
+            }
+            if let Some(capture) = config.capture {
+                actions.push(TriggerAction::Capture(capture));
+            }
+            if let Some(marker) = &config.marker {
+                actions.push(TriggerAction::Marker(marker.clone()));
+            }
+            if let Some(dtr) = config.dtr {
+                actions.push(TriggerAction::Dtr(dtr));
+            }
+            if let Some(run) = &config.run {
+                actions.push(TriggerAction::Run(run.clone()));
+            }
+            if actions.is_empty() {
+                bail!("Trigger '{}' has no action", config.regex);
+            }
+
+            triggers.push(Trigger {
+                regex,
+                actions,
+                once: config.once,
+                fired: false,
+                rx: String::new(),
+            });
+        }
+        Ok(Triggers { triggers })
+    }
+
+    /// Received data, returns the actions of the triggers that matched
+    pub fn feed(&mut self, data: &[u8]) -> Vec<TriggerAction> {
+        let mut actions = Vec::new();
+        if self.triggers.is_empty() {
+            return actions;
+        }
+        let text = String::from_utf8_lossy(data);
+
+        for trigger in self.triggers.iter_mut().filter(|t| !(t.once && t.fired)) {
+            trigger.rx.push_str(&text);
+
+            /* Forget what matched, so it doesn't fire again */
+            while let Some(found) = trigger.regex.find(&trigger.rx) {
+                let end = found.end();
+                trigger.rx.drain(..end);
+                trigger.fired = true;
+                actions.extend(trigger.actions.iter().cloned());
+                if trigger.once || end == 0 {
+                    break;
+                }
+            }
+
+            trim_rx(&mut trigger.rx, MAX_RX_BUFFER);
+        }
+        actions
+    }
+}
+
+/// Start a local command without waiting for it
+pub fn run_command(cmd: &str) -> Result<()> {
+    #[cfg(unix)]
+    let mut command = std::process::Command::new("sh");
+    #[cfg(unix)]
+    command.arg("-c");
+    #[cfg(windows)]
+    let mut command = std::process::Command::new("cmd");
+    #[cfg(windows)]
+    command.arg("/C");
+
+    let mut child = command
+        .arg(cmd)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .with_context(|| format!("Could not run '{}'", cmd))?;
+
+    /* Reap it when it is done */
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trigger::{CaptureAction, DtrAction, TriggerAction, TriggerConfig, Triggers};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Config {
+        trigger: Vec<TriggerConfig>,
+    }
+
+    fn triggers(toml: &str) -> anyhow::Result<Triggers> {
+        let config: Config = toml::from_str(toml).unwrap();
+        Triggers::from_config(&config.trigger)
+    }
+
+    fn feed(triggers: &mut Triggers, data: &str) -> Vec<String> {
+        triggers
+            .feed(data.as_bytes())
+            .into_iter()
+            .map(|action| match action {
+                TriggerAction::Send(data) => format!("send {:?}", String::from_utf8_lossy(&data)),
+                TriggerAction::Bell => "bell".to_string(),
+                TriggerAction::Capture(CaptureAction::Start) => "capture start".to_string(),
+                TriggerAction::Capture(CaptureAction::Stop) => "capture stop".to_string(),
+                TriggerAction::Marker(marker) => format!("marker {}", marker),
+                TriggerAction::Dtr(DtrAction::On) => "dtr on".to_string(),
+                TriggerAction::Dtr(DtrAction::Off) => "dtr off".to_string(),
+                TriggerAction::Dtr(DtrAction::Toggle) => "dtr toggle".to_string(),
+                TriggerAction::Run(cmd) => format!("run {}", cmd),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matching() {
+        let mut triggers = triggers(
+            r#"
+            [[trigger]]
+            regex = 'autoboot'
+            send = ' \x03'
+            once = true
+
+            [[trigger]]
+            regex = 'panic'
+            bell = true
+            marker = 'PANIC'
+            capture = "start"
+            dtr = "toggle"
+            run = 'true'
+            "#,
+        )
+        .unwrap();
+
+        /* Matches split over reads */
+        assert_eq!(feed(&mut triggers, "Hit any key to stop auto"), Vec::<String>::new());
+        assert_eq!(feed(&mut triggers, "boot: 3\r\n"), [r#"send " \u{3}""#]);
+        assert_eq!(feed(&mut triggers, "autoboot again\r\n"), Vec::<String>::new());
+
+        /* Every match fires, but only once */
+        assert_eq!(
+            feed(&mut triggers, "panic panic"),
+            ["bell", "capture start", "marker PANIC", "dtr toggle", "run true"].repeat(2)
+        );
+        assert_eq!(feed(&mut triggers, "\r\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn config_errors() {
+        let error = |toml: &str| format!("{:#}", triggers(toml).err().unwrap());
+        assert_eq!(error("[[trigger]]\nregex = 'x'"), "Trigger 'x' has no action");
+        assert!(error("[[trigger]]\nregex = '('\nbell = true").starts_with("Invalid trigger regex '('"));
+        assert!(error("[[trigger]]\nregex = 'x'\nsend = '\\q'").starts_with("Invalid send for trigger 'x'"));
+    }
+}
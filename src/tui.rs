@@ -1,67 +1,226 @@
 use crate::app::Timestamp;
+use crate::decoder::{LineDecoder, Segment};
+use crate::filter::LineFilter;
+use crate::highlight::Highlighter;
+use crate::input::LineEditor;
+use crate::plot::Plot;
 use anyhow::Result;
 use crossterm::{cursor, execute, queue, style::Print, terminal, tty::IsTty};
-use ratatui::{backend::CrosstermBackend, layout::{Constraint, Direction, Layout}, widgets::{Block, Borders, Paragraph}, Frame, Terminal};
+use ratatui::{backend::CrosstermBackend, layout::{Constraint, Direction, Layout, Rect}, style::{Color, Modifier, Style}, symbols::Marker, text::{Line, Span}, widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, Paragraph}, Frame, Terminal, TerminalOptions, Viewport};
 use std::{collections::VecDeque, io::{stdout, Stdout, Write}};
-use time::{macros::format_description, OffsetDateTime};
+use time::{macros::format_description, OffsetDateTime, UtcOffset};
 
-struct ToPrint {
-    time: OffsetDateTime,
-    str: String,
+struct Prompt {
+    label: String,
+    text: String,
+    cursor: usize,
+}
+
+/* Lines shown above the status line, e.g. the GNSS status */
+struct Panel {
+    title: String,
+    lines: Vec<String>,
 }
 
 pub struct Tui {
     is_tty: bool,
     stdout: std::io::Stdout,
-    // status_msg: String,
+    status_msg: String,
+    status_info: String,
+    prompt: Option<Prompt>,
+    /* The cursor is parked on the prompt instead of the serial output */
+    cursor_on_status: bool,
+    /* Last lines of the screen, below the scroll region (None when not a tty) */
+    status_terminal: Option<Terminal<CrosstermBackend<Stdout>>>,
+    status_area: Rect,
+    panel: Option<Panel>,
+    /* Where the panel is drawn, empty when there is no room or no panel */
+    panel_area: Rect,
     on_alternate_screen: bool,
 
+    decoder: LineDecoder,
+    filter: Option<LineFilter>,
+    /* Whether the output (after the filter) is at the start of a line */
     on_newline: bool,
+    highlighter: Highlighter,
     prefix_timestamp: Timestamp,
+    local_offset: UtcOffset,
+    /* For the elapsed and delta timestamps */
+    start_time: OffsetDateTime,
+    line_time: OffsetDateTime,
 
-    queue: VecDeque<ToPrint>,
+    queue: VecDeque<Segment>,
+    /* Bytes of the oldest queued output that didn't fit */
+    dropped: usize,
 
     // cur_row: u16,
     terminal: Terminal<CrosstermBackend<Stdout>>,
 }
 
+/* Output is kept while on the alternate screen, only the most recent after a
+ * long stay, with a marker where the rest was */
+const MAX_QUEUED: usize = 10000;
+
+const PLOT_COLORS: [Color; 6] = [Color::Yellow, Color::Cyan, Color::Magenta, Color::Green, Color::Red, Color::Blue];
+
 const FORMAT_SIMPLE: &[time::format_description::FormatItem<'static>] =
     format_description!(version = 2, r"\[[hour]:[minute]:[second]\] ");
 const FORMAT_EXTENDED: &[time::format_description::FormatItem<'static>] = format_description!(
     version = 2,
     r"\[[hour]:[minute]:[second].[subsecond digits:3]\] "
 );
-
-struct PrintTime(
-    pub OffsetDateTime,
-    pub &'static [time::format_description::FormatItem<'static>],
+const FORMAT_ISO: &[time::format_description::FormatItem<'static>] = format_description!(
+    version = 2,
+    r"\[[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3][offset_hour sign:mandatory]:[offset_minute]\] "
 );
-impl crossterm::Command for PrintTime {
+
+/* Limit scrolling to rows top..=bottom, keeps the status line in place (DECSTBM) */
+struct SetScrollRegion(u16, u16);
+impl crossterm::Command for SetScrollRegion {
+    fn write_ansi(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
+        write!(f, "\x1b[{};{}r", self.0 + 1, self.1 + 1)
+    }
+}
+
+struct ResetScrollRegion;
+impl crossterm::Command for ResetScrollRegion {
     fn write_ansi(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
-        let s = self.0.format(self.1).unwrap();
-        f.write_str(&s)
+        f.write_str("\x1b[r")
     }
 }
 
 impl Tui {
-    pub fn init() -> Result<Tui> {
+    pub fn init(local_offset: UtcOffset, highlighter: Highlighter) -> Result<Tui> {
         let out = stdout();
         // TODO: Always enable raw? or only on is_tty? (cant remember)
         terminal::enable_raw_mode()?;
 
         let term = Terminal::new(CrosstermBackend::new(stdout()))?;
 
-        Ok(Tui {
+        let mut tui = Tui {
             is_tty: out.is_tty(),
             stdout: out,
-            // status_msg: "".to_string(),
+            status_msg: "".to_string(),
+            status_info: "".to_string(),
+            prompt: None,
+            cursor_on_status: false,
+            status_terminal: None,
+            status_area: Rect::default(),
+            panel: None,
+            panel_area: Rect::default(),
             on_alternate_screen: false,
-            on_newline: false,
+            decoder: LineDecoder::default(),
+            filter: None,
+            on_newline: true,
+            highlighter,
             prefix_timestamp: Timestamp::Off,
+            local_offset,
+            start_time: OffsetDateTime::now_utc(),
+            line_time: OffsetDateTime::now_utc(),
             // cur_row: 0,
             queue: VecDeque::new(),
+            dropped: 0,
             terminal: term,
-        })
+        };
+        tui.setup_status_line()?;
+
+        Ok(tui)
+    }
+
+    /// Reserve the last line for the status, and the lines above it for the
+    /// panel, by limiting the scroll region
+    fn setup_status_line(&mut self) -> Result<()> {
+        if !self.is_tty {
+            return Ok(());
+        }
+
+        let (cols, rows) = terminal::size()?;
+        if rows < 2 {
+            self.status_terminal = None;
+            return Ok(());
+        }
+
+        /* The panel has a title line, leave at least two lines for the output */
+        let panel_height = match &self.panel {
+            Some(panel) if rows >= panel.lines.len() as u16 + 4 => panel.lines.len() as u16 + 1,
+            _ => 0,
+        };
+        let old_height = self.panel_area.height;
+
+        /* Setting the region moves the cursor home, so save and restore it */
+        if self.cursor_on_status {
+            queue!(self.stdout, cursor::RestorePosition)?;
+        }
+        /* Move the output out of the way of a bigger panel */
+        if panel_height > old_height && self.status_area.y == rows - 1 {
+            let grow = panel_height - old_height;
+            queue!(self.stdout, terminal::ScrollUp(grow), cursor::MoveUp(grow))?;
+        }
+        /* Rows of a smaller panel become output rows */
+        if panel_height < old_height && self.status_area.y == rows - 1 {
+            queue!(self.stdout, cursor::SavePosition)?;
+            for row in self.panel_area.y..rows - 1 - panel_height {
+                queue!(self.stdout, cursor::MoveTo(0, row), terminal::Clear(terminal::ClearType::CurrentLine))?;
+            }
+            queue!(self.stdout, cursor::RestorePosition)?;
+        }
+        queue!(self.stdout, cursor::SavePosition, SetScrollRegion(0, rows - 2 - panel_height))?;
+        self.stdout.flush()?;
+        self.cursor_on_status = false;
+
+        /* When shrinking, the cursor might end up where the status line goes */
+        let shrunk = self.status_area.height > 0 && self.status_area.y > rows - 1;
+
+        /* Fixed viewports don't follow resizes, so just create a new one */
+        self.status_area = Rect::new(0, rows - 1, cols, 1);
+        self.panel_area = Rect::new(0, rows - 1 - panel_height, cols, panel_height);
+        let options = TerminalOptions {
+            viewport: Viewport::Fixed(self.panel_area.union(self.status_area)),
+        };
+        let mut status_terminal =
+            Terminal::with_options(CrosstermBackend::new(stdout()), options)?;
+        status_terminal.clear()?;
+        self.status_terminal = Some(status_terminal);
+        execute!(self.stdout, cursor::RestorePosition)?;
+        if shrunk {
+            /* Continue on a fresh line at the bottom of the output */
+            execute!(self.stdout, terminal::ScrollUp(1), cursor::MoveTo(0, rows - 2 - panel_height))?;
+        }
+
+        self.draw_status()
+    }
+
+    fn draw_status(&mut self) -> Result<()> {
+        if self.on_alternate_screen {
+            return Ok(());
+        }
+        let Some(status_terminal) = self.status_terminal.as_mut() else {
+            return Ok(());
+        };
+
+        if !self.cursor_on_status {
+            queue!(self.stdout, cursor::SavePosition)?;
+            self.stdout.flush()?;
+        }
+
+        let msg = &self.status_msg;
+        let info = &self.status_info;
+        let prompt = &self.prompt;
+        let (status_area, panel_area) = (self.status_area, self.panel_area);
+        let panel = self.panel.as_ref().filter(|_| panel_area.height > 0);
+        status_terminal.draw(|frame| {
+            if let Some(panel) = panel {
+                panel_ui(frame, panel_area, panel);
+            }
+            status_ui(frame, status_area, msg, info, prompt.as_ref());
+        })?;
+
+        /* The terminal shows the cursor on the prompt, otherwise it goes back to the output */
+        self.cursor_on_status = self.prompt.is_some();
+        if !self.cursor_on_status {
+            execute!(self.stdout, cursor::RestorePosition, cursor::Show)?;
+        }
+        Ok(())
     }
 
     pub fn cleanup(&mut self) -> Result<()> {
@@ -70,10 +229,19 @@ impl Tui {
         /* Print a newline as we don't know where the serial output ended */
         if self.is_tty {
             let (_cols, rows) = terminal::size()?;
-            execute!(self.stdout, cursor::MoveTo(0, rows - 1), Print("\r\n"))?;
+            self.status_terminal = None;
+            execute!(
+                self.stdout,
+                ResetScrollRegion,
+                cursor::MoveTo(0, rows - 1),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                Print("\r\n")
+            )?;
         } else {
             print!("\r\n");
         }
+        /* Whatever is printed after us needs \n to start a line again */
+        terminal::disable_raw_mode()?;
 
         Ok(())
     }
@@ -108,42 +276,65 @@ impl Tui {
     // }
 
     fn flush_print_queue(&mut self) -> Result<()> {
-        while !self.queue.is_empty() {
-            let item = self.queue.pop_front().unwrap();
-            self.print_line(item.time, &item.str)?;
+        let mut segments: Vec<Segment> = self.queue.drain(..).collect();
+        if self.dropped > 0 {
+            let text = format!("\r\n--- {} bytes dropped ---\r\n", std::mem::take(&mut self.dropped));
+            segments.insert(0, Segment { start: None, text });
         }
-        Ok(())
+        self.print_segments(&segments)
     }
 
-    fn print_line(&mut self, time: OffsetDateTime, str: &str) -> Result<()> {
+    fn print_segments(&mut self, segments: &[Segment]) -> Result<()> {
         assert!(!self.on_alternate_screen);
 
-        let split = str.split_inclusive('\n');
-        for line in split {
-            if self.on_newline && self.prefix_timestamp != Timestamp::Off {
-                let format = match self.prefix_timestamp {
-                    Timestamp::Simple => FORMAT_SIMPLE,
-                    Timestamp::Extend => FORMAT_EXTENDED,
-                    Timestamp::Off => unreachable!(),
-                };
-                queue!(self.stdout, PrintTime(time, format), Print(line))?;
-            } else {
-                queue!(self.stdout, Print(line))?;
+        /* Go back to the output, the prompt cursor is saved below */
+        let prompt_cursor = match &self.prompt {
+            Some(prompt) if self.cursor_on_status => {
+                queue!(self.stdout, cursor::RestorePosition)?;
+                Some(prompt_cursor(self.status_area, prompt))
             }
-
-            if line.ends_with('\n') {
-                self.on_newline = true;
-                // self.cur_row += 1;
-            } else {
-                self.on_newline = false;
+            _ => None,
+        };
+
+        for segment in segments {
+            if let Some(time) = segment.start {
+                let prefix = self.format_timestamp(time)?;
+                self.line_time = time;
+                queue!(self.stdout, Print(prefix))?;
             }
-            // Handle the last line so we don't overwrite the status line
-            // self.handle_last_line()?;
+            let text = self.highlighter.highlight(&segment.text);
+            queue!(self.stdout, Print(text))?;
+        }
+        if let Some((col, row)) = prompt_cursor {
+            queue!(self.stdout, cursor::SavePosition, cursor::MoveTo(col, row))?;
         }
         self.stdout.flush()?;
         Ok(())
     }
 
+    fn format_timestamp(&self, time: OffsetDateTime) -> Result<String> {
+        let since = |earlier: OffsetDateTime| {
+            let duration = (time - earlier).max(time::Duration::ZERO);
+            (duration.whole_seconds(), duration.subsec_milliseconds())
+        };
+
+        let prefix = match self.prefix_timestamp {
+            Timestamp::Simple => time.format(FORMAT_SIMPLE)?,
+            Timestamp::Extend => time.format(FORMAT_EXTENDED)?,
+            Timestamp::Iso => time.format(FORMAT_ISO)?,
+            Timestamp::Elapsed => {
+                let (secs, millis) = since(self.start_time);
+                format!("[{:>6}.{:03}] ", secs, millis)
+            }
+            Timestamp::Delta => {
+                let (secs, millis) = since(self.line_time);
+                format!("[+{:>4}.{:03}] ", secs, millis)
+            }
+            Timestamp::Off => String::new(),
+        };
+        Ok(prefix)
+    }
+
     pub fn print_to_screen(&mut self, str: &str) -> Result<()> {
         assert!(!self.on_alternate_screen);
 
@@ -151,71 +342,174 @@ impl Tui {
         Ok(())
     }
 
+    /// Print a local message on a line of its own
+    pub fn print_message(&mut self, msg: &str) -> Result<()> {
+        let mut str = if self.on_newline {
+            String::new()
+        } else {
+            "\r\n".to_string()
+        };
+        str += msg;
+        str += "\r\n";
+        self.print_or_queue(&str)
+    }
+
+    /// Current local time
+    pub fn now(&self) -> OffsetDateTime {
+        /* now_local() doesn't work once there are threads, the offset is taken at startup */
+        OffsetDateTime::now_utc().to_offset(self.local_offset)
+    }
+
     pub fn print_or_queue(&mut self, str: &str) -> Result<()> {
-        // TODO OffsetDateTime::now_local() fails as it is not thread safe
-        let time = OffsetDateTime::now_utc();
+        let time = self.now();
+        let segments = self.decoder.decode_text(str.as_bytes(), time);
+        self.print_or_queue_segments(segments)
+    }
 
-        if self.on_alternate_screen {
-            self.queue.push_back(ToPrint {time, str: str.to_string()});
-        } else {
-            self.print_line(time, str)?;
+    /// Print received data, `time` is when it arrived
+    pub fn print_data(&mut self, data: &[u8], time: OffsetDateTime) -> Result<()> {
+        let mut segments = self.decoder.decode(data, time);
+        if let Some(filter) = self.filter.as_mut() {
+            segments = filter.filter(segments);
         }
+        self.print_or_queue_segments(segments)
+    }
 
+    /// End the current line, e.g. after the line went idle
+    pub fn break_line(&mut self) -> Result<()> {
+        let mut segments = self.decoder.break_line().into_iter().collect();
+        if let Some(filter) = self.filter.as_mut() {
+            segments = filter.filter(segments);
+        }
+        self.print_or_queue_segments(segments)
+    }
+
+    /// Only show the lines that pass the filter, None shows everything
+    pub fn set_filter(&mut self, filter: Option<LineFilter>) -> Result<()> {
+        if let Some(mut old) = self.filter.take() {
+            let segments = old.flush();
+            self.print_or_queue_segments(segments)?;
+        }
+        self.filter = filter;
         Ok(())
     }
 
-    pub fn set_status_msg(&mut self, _str: &str) -> Result<()> {
-        // if self.is_tty {
-        //     let (_cols, rows) = terminal::size()?;
-        //     execute!(
-        //         self.stdout,
-        //         cursor::SavePosition,
-        //         cursor::MoveTo(0, rows - 1),
-        //         terminal::Clear(terminal::ClearType::CurrentLine),
-        //         Print(str),
-        //         cursor::RestorePosition
-        //     )?;
-
-        //     self.status_msg = str.to_string();
-        // }
+    /// Show what the filter holds back of the current line, e.g. a prompt
+    pub fn flush_filter(&mut self) -> Result<()> {
+        let Some(filter) = self.filter.as_mut() else {
+            return Ok(());
+        };
+        let segments = filter.flush();
+        self.print_or_queue_segments(segments)
+    }
+
+    pub fn filter(&self) -> Option<&LineFilter> {
+        self.filter.as_ref()
+    }
+
+    pub fn bell(&mut self) -> Result<()> {
+        execute!(self.stdout, Print("\x07"))?;
         Ok(())
     }
 
-    pub fn set_status(&mut self, _prefix: &str, _val: &str) -> Result<()> {
-        // if self.is_tty {
-        //     // TODO fix set_status_msg api
-        //     let msg = prefix.to_owned() + val;
-        //     self.set_status_msg(&msg)?;
-        // }
+    pub fn set_hex(&mut self, hex: bool) -> Result<()> {
+        self.break_line()?;
+        self.decoder.set_hex(hex);
         Ok(())
     }
 
-    pub fn hide_status(&mut self) -> Result<()> {
-        // if self.is_tty {
-        //     let (_cols, rows) = terminal::size()?;
-        //     execute!(
-        //         self.stdout,
-        //         cursor::SavePosition,
-        //         cursor::MoveTo(0, rows - 1),
-        //         terminal::Clear(terminal::ClearType::CurrentLine),
-        //         cursor::RestorePosition
-        //     )?;
-
-        //     self.status_msg = "".to_string();
-        // }
+    fn print_or_queue_segments(&mut self, segments: Vec<Segment>) -> Result<()> {
+        if let Some(last) = segments.last() {
+            self.on_newline = last.text.ends_with('\n');
+        }
+        if self.on_alternate_screen {
+            self.queue.extend(segments);
+            while self.queue.len() > MAX_QUEUED {
+                if let Some(segment) = self.queue.pop_front() {
+                    self.dropped += segment.text.len();
+                }
+            }
+        } else {
+            self.print_segments(&segments)?;
+        }
         Ok(())
     }
 
+    pub fn set_status_msg(&mut self, str: &str) -> Result<()> {
+        self.status_msg = str.to_string();
+        self.draw_status()
+    }
+
+    pub fn set_status(&mut self, prefix: &str, val: &str) -> Result<()> {
+        let msg = prefix.to_owned() + val;
+        self.set_status_msg(&msg)
+    }
+
+    pub fn hide_status(&mut self) -> Result<()> {
+        self.status_msg.clear();
+        self.draw_status()
+    }
+
+    /// Permanent information on the right side of the status line
+    pub fn set_status_info(&mut self, str: &str) -> Result<()> {
+        self.status_info = str.to_string();
+        self.draw_status()
+    }
+
+    pub fn show_prompt(&mut self, label: &str, editor: &LineEditor) -> Result<()> {
+        self.prompt = Some(Prompt {
+            label: label.to_string(),
+            text: editor.text().to_string(),
+            cursor: editor.cursor(),
+        });
+        self.draw_status()
+    }
+
+    /// Show `lines` above the status line, the panel goes away without lines
+    pub fn set_panel(&mut self, title: &str, lines: Vec<String>) -> Result<()> {
+        let old_len = self.panel.as_ref().map(|panel| panel.lines.len());
+        let new_len = Some(lines.len()).filter(|&len| len > 0);
+        self.panel = new_len.map(|_| Panel {
+            title: title.to_string(),
+            lines,
+        });
+
+        /* The help screen sets things up again when it is done */
+        if self.on_alternate_screen {
+            return Ok(());
+        }
+        match old_len == new_len {
+            true => self.draw_status(),
+            false => self.setup_status_line(),
+        }
+    }
+
+    pub fn hide_prompt(&mut self) -> Result<()> {
+        self.prompt = None;
+        self.draw_status()
+    }
+
     pub fn clear_screen(&mut self) -> Result<()> {
         assert!(!self.on_alternate_screen);
 
+        if self.cursor_on_status {
+            queue!(self.stdout, cursor::RestorePosition)?;
+            self.cursor_on_status = false;
+        }
         execute!(
             self.stdout,
             terminal::Clear(terminal::ClearType::All),
             cursor::MoveTo(0, 0)
         )?;
         // self.cur_row = 0;
-        Ok(())
+
+        /* The status line got cleared as well */
+        if let Some(status_terminal) = self.status_terminal.as_mut() {
+            execute!(self.stdout, cursor::SavePosition)?;
+            status_terminal.clear()?;
+            execute!(self.stdout, cursor::RestorePosition)?;
+        }
+        self.draw_status()
     }
 
     pub fn set_prefix_timestamp(&mut self, timestamp: Timestamp) {
@@ -224,6 +518,10 @@ impl Tui {
 
     pub fn enter_alt(&mut self) -> Result<()> {
         if !self.on_alternate_screen {
+            if self.cursor_on_status {
+                queue!(self.stdout, cursor::RestorePosition)?;
+                self.cursor_on_status = false;
+            }
             execute!(
                 self.stdout,
                 cursor::SavePosition,
@@ -247,6 +545,8 @@ impl Tui {
                 cursor::Show,
             )?;
             self.on_alternate_screen = false;
+            /* The size might have changed while we were away */
+            self.setup_status_line()?;
             self.flush_print_queue()?;
         }
         Ok(())
@@ -256,43 +556,182 @@ impl Tui {
         self.on_alternate_screen
     }
 
-    pub fn draw_ui(&mut self) -> Result<()> {
+    pub fn draw_help(&mut self, help: &[(String, String)]) -> Result<()> {
         assert!(self.on_alternate_screen);
-        self.terminal.draw(ui)?;
+        self.terminal.draw(|frame| help_ui(frame, help))?;
         Ok(())
     }
 
-    pub fn resize(&mut self) -> Result<()> {
+    pub fn draw_plot(&mut self, plot: &Plot) -> Result<()> {
         assert!(self.on_alternate_screen);
-        self.terminal.autoresize()?;
+        self.terminal.draw(|frame| plot_ui(frame, plot))?;
+        Ok(())
+    }
+
+    pub fn draw_stats(&mut self, stats: &[(String, String)]) -> Result<()> {
+        assert!(self.on_alternate_screen);
+        self.terminal.draw(|frame| stats_ui(frame, stats))?;
+        Ok(())
+    }
+
+    pub fn resize(&mut self) -> Result<()> {
+        if self.on_alternate_screen {
+            self.terminal.autoresize()?;
+        } else {
+            self.setup_status_line()?;
+        }
         Ok(())
     }
 }
 
-fn ui(frame: &mut Frame) {
-    // frame.render_widget(
-    //     Paragraph::new("Hello World!")
-    //         .block(Block::default().title("Greeting").borders(Borders::ALL)),
-    //     frame.size(),
-    // );
-    let inner_layout = Layout::new(
-        Direction::Horizontal, [
-            Constraint::Percentage(33), Constraint::Percentage(33), Constraint::Percentage(33)],
-    ).split(frame.size());
-    frame.render_widget(
-        Block::default().borders(Borders::ALL).title("Left"),
-        inner_layout[0],
-    );
-    frame.render_widget(
-        Block::default().borders(Borders::ALL).title("mid"),
-        inner_layout[1],
-    );
+fn prompt_cursor(area: Rect, prompt: &Prompt) -> (u16, u16) {
+    let offset = prompt.label.chars().count() + 1 + prompt.cursor;
+    let x = (area.x as usize + offset).min(area.right().saturating_sub(1) as usize);
+    (x as u16, area.y)
+}
+
+fn panel_ui(frame: &mut Frame, area: Rect, panel: &Panel) {
+    let lines: Vec<Line> = panel.lines.iter().map(|line| Line::from(format!(" {}", line))).collect();
+    let block = Block::default().borders(Borders::TOP).title(format!(" {} ", panel.title));
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn status_ui(frame: &mut Frame, area: Rect, msg: &str, info: &str, prompt: Option<&Prompt>) {
+    let style = Style::default().add_modifier(Modifier::REVERSED);
+
+    if let Some(prompt) = prompt {
+        let line = Line::from(vec![
+            Span::styled(prompt.label.clone(), style),
+            Span::raw(" "),
+            Span::raw(prompt.text.clone()),
+        ]);
+        frame.render_widget(Paragraph::new(line), area);
+
+        /* Status messages still show up, on the right */
+        if !msg.is_empty() {
+            let width = (msg.chars().count() as u16 + 2).min(area.width);
+            let msg_area = Rect::new(area.right() - width, area.y, width, 1);
+            frame.render_widget(Paragraph::new(format!(" {} ", msg)).style(style), msg_area);
+        }
+
+        let (x, y) = prompt_cursor(area, prompt);
+        frame.set_cursor(x, y);
+        return;
+    }
+
+    let info_width = info.chars().count() as u16;
+    let layout = Layout::new(
+        Direction::Horizontal,
+        [Constraint::Min(0), Constraint::Length(info_width + 1)],
+    )
+    .split(area);
+    frame.render_widget(Paragraph::new(format!(" {}", msg)).style(style), layout[0]);
+    frame.render_widget(Paragraph::new(format!("{} ", info)).style(style), layout[1]);
+}
+
+fn help_ui(frame: &mut Frame, help: &[(String, String)]) {
+    let key_width = help.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+    let mut lines: Vec<Line> = help
+        .iter()
+        .map(|(key, desc)| {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {:>width$}  ", key, width = key_width),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(desc.clone()),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(" Press any key to continue"));
+
     frame.render_widget(
-        Block::default().borders(Borders::ALL).title("Right"),
-        inner_layout[2],
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Help")),
+        frame.size(),
     );
 }
 
+fn stats_ui(frame: &mut Frame, stats: &[(String, String)]) {
+    let label_width = stats.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+    let lines: Vec<Line> = stats
+        .iter()
+        .map(|(label, value)| {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {:<width$}  ", label, width = label_width),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(value.clone()),
+            ])
+        })
+        .collect();
+
+    let block = Block::default().borders(Borders::ALL).title("Statistics (press any key to continue)");
+    frame.render_widget(Paragraph::new(lines).block(block), frame.size());
+}
+
+fn format_value(value: f64) -> String {
+    if value.abs() >= 1000.0 || value == value.trunc() {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn plot_ui(frame: &mut Frame, plot: &Plot) {
+    let block = Block::default().borders(Borders::ALL).title("Plot (press any key to continue)");
+    let series: Vec<(String, Vec<(f64, f64)>)> = plot
+        .series()
+        .iter()
+        .filter_map(|series| {
+            let (_, last) = series.points.back()?;
+            let name = format!("{} {}", series.name, format_value(*last));
+            Some((name, series.points.iter().copied().collect()))
+        })
+        .collect();
+    if series.is_empty() {
+        let text = " Nothing to plot yet, lines like \"temp=23.4,hum=41\" are plotted";
+        frame.render_widget(Paragraph::new(text).block(block), frame.size());
+        return;
+    }
+
+    /* Scroll once the window is full, scale to what is visible */
+    let now = plot.now();
+    let x_bounds = [(now - plot.window()).max(0.0), now.max(plot.window())];
+    let values = series.iter().flat_map(|(_, points)| points.iter().map(|&(_, value)| value));
+    let (mut min, mut max) = values.fold((f64::MAX, f64::MIN), |(min, max), value| (min.min(value), max.max(value)));
+    let margin = match max - min {
+        range if range > 0.0 => range * 0.05,
+        _ => min.abs().max(1.0) * 0.1,
+    };
+    min -= margin;
+    max += margin;
+
+    let datasets = series
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, points))| {
+            Dataset::default()
+                .name(name.clone())
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(PLOT_COLORS[idx % PLOT_COLORS.len()]))
+                .data(points)
+        })
+        .collect();
+    let x_axis = Axis::default().bounds(x_bounds).labels(vec![
+        Span::raw(format!("{:.0}s", x_bounds[0])),
+        Span::raw(format!("{:.0}s", x_bounds[1])),
+    ]);
+    let y_axis = Axis::default().bounds([min, max]).labels(vec![
+        Span::raw(format_value(min)),
+        Span::raw(format_value((min + max) / 2.0)),
+        Span::raw(format_value(max)),
+    ]);
+    frame.render_widget(Chart::new(datasets).block(block).x_axis(x_axis).y_axis(y_axis), frame.size());
+}
+
 impl Drop for Tui {
     fn drop(&mut self) {
         /* Ignore errors here */
@@ -300,6 +739,9 @@ impl Drop for Tui {
         // (logic is a bit entangled)
         // TODO: if self.is_tty ?
         let _ = self.leave_alt();
+        if self.is_tty {
+            let _ = execute!(self.stdout, ResetScrollRegion);
+        }
         let _ = terminal::disable_raw_mode();
     }
 }
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::{SerialPort, SerialStream};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Simulator {
+    /// Send every byte straight back
+    Loopback,
+    /// Act like a tiny shell: echo input and answer every line
+    Responder,
+}
+
+/// The other end of the pty pair, must live as long as our end
+pub struct Peer {
+    pub name: String,
+    /* Keep the peer open, reading the master fails when no one has it open */
+    _port: Option<SerialStream>,
+}
+
+/// Create a pty pair, returns our end (replaces the serial port) and the peer
+pub fn open(simulator: Option<Simulator>) -> Result<(SerialStream, Peer)> {
+    let (port, mut peer) = SerialStream::pair()?;
+    peer.set_exclusive(false)?;
+    let name = peer.name().unwrap_or_default();
+
+    let peer_port = match simulator {
+        Some(simulator) => {
+            tokio::spawn(async move {
+                /* Nothing sensible to do with errors, the simulator just stops */
+                let _ = match simulator {
+                    Simulator::Loopback => loopback(peer).await,
+                    Simulator::Responder => responder(peer).await,
+                };
+            });
+            None
+        }
+        None => Some(peer),
+    };
+
+    Ok((
+        port,
+        Peer {
+            name,
+            _port: peer_port,
+        },
+    ))
+}
+
+async fn loopback(mut peer: SerialStream) -> Result<()> {
+    let mut buf: [u8; 128] = [0; 128];
+    loop {
+        let read_bytes = peer.read(&mut buf).await?;
+        /* Our end went away */
+        if read_bytes == 0 {
+            return Ok(());
+        }
+        peer.write_all(&buf[0..read_bytes]).await?;
+    }
+}
+
+async fn responder(mut peer: SerialStream) -> Result<()> {
+    const PROMPT: &[u8] = b"sim> ";
+    let mut buf: [u8; 128] = [0; 128];
+    let mut line = Vec::new();
+    let mut last_cr = false;
+
+    peer.write_all(b"minircom simulator, every line is answered\r\n").await?;
+    peer.write_all(PROMPT).await?;
+    loop {
+        let read_bytes = peer.read(&mut buf).await?;
+        if read_bytes == 0 {
+            return Ok(());
+        }
+        for &byte in &buf[0..read_bytes] {
+            /* Treat CRLF as a single line ending */
+            let skip = last_cr && byte == b'\n';
+            last_cr = byte == b'\r';
+            if skip {
+                continue;
+            }
+
+            match byte {
+                b'\r' | b'\n' => {
+                    let answer = format!("\r\nreceived {} bytes: ", line.len());
+                    peer.write_all(answer.as_bytes()).await?;
+                    peer.write_all(&line).await?;
+                    peer.write_all(b"\r\n").await?;
+                    peer.write_all(PROMPT).await?;
+                    line.clear();
+                }
+                b'\x08' | b'\x7f' => {
+                    if line.pop().is_some() {
+                        peer.write_all(b"\x08 \x08").await?;
+                    }
+                }
+                _ => {
+                    line.push(byte);
+                    peer.write_all(&[byte]).await?;
+                }
+            }
+        }
+    }
+}